@@ -0,0 +1,27 @@
+use crate::commands::ssh_commands::SSHManagerState;
+use crate::ssh::error::SSHError;
+use crate::ssh::types::{BatchRequest, BatchResult, ExecRequest, ExecResult};
+use tauri::State;
+
+/// Runs a one-off command on a connection without opening a terminal, returning its
+/// output, exit status or signal and duration. A command that runs longer than the request
+/// allows is stopped and comes back marked `timed_out` with whatever it printed.
+#[tauri::command]
+pub async fn exec_command(
+    connection_id: String,
+    request: ExecRequest,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<ExecResult, SSHError> {
+    ssh_manager.exec(&connection_id, request).await
+}
+
+/// Runs a command on several connections, picked by id or by tag, and returns a row per
+/// host. Saved hosts are connected for the batch if they aren't already. Progress arrives
+/// on the batch's event as each host starts and finishes.
+#[tauri::command]
+pub async fn run_batch(
+    request: BatchRequest,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<BatchResult, SSHError> {
+    ssh_manager.run_batch(request).await
+}
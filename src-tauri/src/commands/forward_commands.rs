@@ -0,0 +1,54 @@
+use crate::commands::ssh_commands::SSHManagerState;
+use crate::ssh::error::SSHError;
+use crate::ssh::types::{DynamicForward, LocalForward, PortForward, RemoteForward};
+use tauri::State;
+
+/// Starts forwarding a local port through a connection (`ssh -L`) and returns the running
+/// forward, including the port actually bound when the rule asked for port 0
+#[tauri::command]
+pub async fn create_port_forward(
+    connection_id: String,
+    rule: LocalForward,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<PortForward, SSHError> {
+    ssh_manager.create_local_forward(&connection_id, rule).await
+}
+
+/// Asks the server to listen on a port and carry its connections back to a local address
+/// (`ssh -R`). The returned forward names the port the server listens on.
+#[tauri::command]
+pub async fn create_remote_port_forward(
+    connection_id: String,
+    rule: RemoteForward,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<PortForward, SSHError> {
+    ssh_manager.create_remote_forward(&connection_id, rule).await
+}
+
+/// Runs a local SOCKS proxy (SOCKS5, or SOCKS4/4a) whose connections are made from the
+/// server (`ssh -D`)
+#[tauri::command]
+pub async fn create_dynamic_port_forward(
+    connection_id: String,
+    rule: DynamicForward,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<PortForward, SSHError> {
+    ssh_manager.create_dynamic_forward(&connection_id, rule).await
+}
+
+#[tauri::command]
+pub async fn list_port_forwards(
+    connection_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<Vec<PortForward>, SSHError> {
+    ssh_manager.list_port_forwards(&connection_id).await
+}
+
+#[tauri::command]
+pub async fn stop_port_forward(
+    connection_id: String,
+    forward_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<(), SSHError> {
+    ssh_manager.stop_port_forward(&connection_id, &forward_id).await
+}
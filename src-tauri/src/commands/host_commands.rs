@@ -0,0 +1,39 @@
+use crate::ssh::error::SSHError;
+use crate::ssh::inventory::HostInventory;
+use crate::ssh::types::SSHConnectionConfig;
+use std::sync::Arc;
+use tauri::State;
+
+// Saved hosts, independent of the SSH manager's live connections
+pub type HostInventoryState = Arc<HostInventory>;
+
+#[tauri::command]
+pub async fn list_hosts(
+    inventory: State<'_, HostInventoryState>,
+) -> Result<Vec<SSHConnectionConfig>, SSHError> {
+    inventory.list().await
+}
+
+#[tauri::command]
+pub async fn create_host(
+    config: SSHConnectionConfig,
+    inventory: State<'_, HostInventoryState>,
+) -> Result<SSHConnectionConfig, SSHError> {
+    inventory.create(config).await
+}
+
+#[tauri::command]
+pub async fn update_host(
+    config: SSHConnectionConfig,
+    inventory: State<'_, HostInventoryState>,
+) -> Result<SSHConnectionConfig, SSHError> {
+    inventory.update(config).await
+}
+
+#[tauri::command]
+pub async fn delete_host(
+    host_id: String,
+    inventory: State<'_, HostInventoryState>,
+) -> Result<(), SSHError> {
+    inventory.delete(&host_id).await
+}
@@ -3,4 +3,16 @@ pub mod ssh_commands;
 pub use ssh_commands::*;
 
 pub mod auth_commands;
-pub use auth_commands::*;
\ No newline at end of file
+pub use auth_commands::*;
+
+pub mod host_commands;
+pub use host_commands::*;
+
+pub mod terminal_commands;
+pub use terminal_commands::*;
+
+pub mod forward_commands;
+pub use forward_commands::*;
+
+pub mod exec_commands;
+pub use exec_commands::*;
@@ -0,0 +1,145 @@
+use crate::commands::ssh_commands::SSHManagerState;
+use crate::ssh::error::SSHError;
+use crate::ssh::types::{BroadcastGroup, BroadcastResult, TerminalReplay, TerminalSession, TerminalSize};
+use tauri::State;
+
+/// Version of the terminal API: the commands below and the `ssh:terminal:*` events.
+///
+/// The frontend reads it once at startup and refuses to drive a backend whose version
+/// differs from the one it was built against. Adding a command, an optional argument or
+/// a result field keeps the version. Removing or renaming any of them, changing a type
+/// or encoding (such as how output bytes are sent), or changing what a command does with
+/// the same arguments bumps it.
+pub const TERMINAL_API_VERSION: u32 = 2;
+
+#[tauri::command]
+pub fn terminal_api_version() -> u32 {
+    TERMINAL_API_VERSION
+}
+
+/// Opens a session running the user's shell in a PTY (80x24 `xterm-256color` by default)
+/// and returns its terminal id; output arrives on the terminal's event
+#[tauri::command]
+pub async fn create_terminal_session(
+    connection_id: String,
+    size: Option<TerminalSize>,
+    term: Option<String>,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<String, SSHError> {
+    ssh_manager.create_terminal_session(&connection_id, size, term).await
+}
+
+#[tauri::command]
+pub async fn send_terminal_input_to_session(
+    connection_id: String,
+    terminal_id: String,
+    data: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<(), SSHError> {
+    ssh_manager
+        .send_terminal_input(&connection_id, &terminal_id, data.as_bytes())
+        .await
+}
+
+#[tauri::command]
+pub async fn resize_terminal_session(
+    connection_id: String,
+    terminal_id: String,
+    size: TerminalSize,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<(), SSHError> {
+    ssh_manager.resize_terminal_session(&connection_id, &terminal_id, size).await
+}
+
+#[tauri::command]
+pub async fn close_terminal_session(
+    connection_id: String,
+    terminal_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<(), SSHError> {
+    ssh_manager.close_terminal_session(&connection_id, &terminal_id).await
+}
+
+#[tauri::command]
+pub async fn get_terminal_session(
+    connection_id: String,
+    terminal_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<TerminalSession, SSHError> {
+    ssh_manager.get_terminal_session(&connection_id, &terminal_id).await
+}
+
+#[tauri::command]
+pub async fn list_terminal_sessions(
+    connection_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<Vec<TerminalSession>, SSHError> {
+    ssh_manager.list_terminal_sessions(&connection_id).await
+}
+
+/// Returns the output a terminal session has kept, for a view mounted after it arrived,
+/// such as on switching back to a tab or opening the terminal in a detached window. Output
+/// events that arrive meanwhile overlap it up to the replay's `offset`.
+#[tauri::command]
+pub async fn replay_terminal_session(
+    connection_id: String,
+    terminal_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<TerminalReplay, SSHError> {
+    ssh_manager.replay_terminal_session(&connection_id, &terminal_id).await
+}
+
+/// Groups terminal sessions, which may be on different connections, so that input can be
+/// typed into all of them at once
+#[tauri::command]
+pub async fn create_broadcast_group(
+    name: String,
+    terminal_ids: Vec<String>,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<BroadcastGroup, SSHError> {
+    ssh_manager.create_broadcast_group(name, terminal_ids).await
+}
+
+#[tauri::command]
+pub async fn join_broadcast_group(
+    group_id: String,
+    terminal_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<BroadcastGroup, SSHError> {
+    ssh_manager.join_broadcast_group(&group_id, &terminal_id).await
+}
+
+#[tauri::command]
+pub async fn leave_broadcast_group(
+    group_id: String,
+    terminal_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<BroadcastGroup, SSHError> {
+    ssh_manager.leave_broadcast_group(&group_id, &terminal_id).await
+}
+
+#[tauri::command]
+pub async fn delete_broadcast_group(
+    group_id: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<(), SSHError> {
+    ssh_manager.delete_broadcast_group(&group_id).await
+}
+
+#[tauri::command]
+pub async fn list_broadcast_groups(
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<Vec<BroadcastGroup>, SSHError> {
+    Ok(ssh_manager.list_broadcast_groups().await)
+}
+
+/// Sends input to every terminal in a broadcast group. Terminals that couldn't take it
+/// are reported in their row of the result; the others still get it.
+#[tauri::command]
+pub async fn send_terminal_input_to_group(
+    group_id: String,
+    data: String,
+    ssh_manager: State<'_, SSHManagerState>,
+) -> Result<BroadcastResult, SSHError> {
+    ssh_manager.broadcast_terminal_input(&group_id, data.as_bytes()).await
+}
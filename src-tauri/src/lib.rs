@@ -1,12 +1,23 @@
-use tauri::{TitleBarStyle, WebviewUrl, WebviewWindowBuilder};
+use tauri::{Emitter, Manager, TitleBarStyle, WebviewUrl, WebviewWindowBuilder};
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 mod ssh;
 mod commands;
 
 use ssh::manager::SSHManager;
+use ssh::events::{EventPayload, EventSink};
 use commands::ssh_commands::SSHManagerState;
+use ssh::inventory::{HostInventory, INVENTORY_FILE_NAME};
+use commands::host_commands::HostInventoryState;
+
+/// Emits SSH events to every webview as Tauri events
+struct AppEventSink(tauri::AppHandle);
+
+impl EventSink for AppEventSink {
+    fn emit(&self, name: &str, payload: &EventPayload) -> Result<(), String> {
+        self.0.emit(name, payload).map_err(|e| e.to_string())
+    }
+}
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -33,7 +44,7 @@ pub fn run() {
 #[cfg(desktop)]
 pub fn run() {
     tauri::Builder::default()
-        .setup(|app| {
+        .setup(move |app| {
             #[cfg(target_os = "macos")]
             let win_builder = WebviewWindowBuilder::new(app, "main", WebviewUrl::default())
                 .title("Hana")
@@ -60,24 +71,55 @@ pub fn run() {
                 e
             })?;
 
+            let data_dir = app.path().app_data_dir()?;
+            let inventory = Arc::new(HostInventory::new(data_dir.join(INVENTORY_FILE_NAME)));
+            let ssh_manager = SSHManager::new().with_inventory(inventory.clone());
+            app.manage(inventory as HostInventoryState);
+            app.manage(Arc::new(ssh_manager.clone()) as SSHManagerState);
+
+            let sink = AppEventSink(app.handle().clone());
+            tauri::async_runtime::spawn(async move { ssh_manager.dispatch_events(sink).await });
+
             Ok(())
         })
-        .manage(Arc::new(Mutex::new(SSHManager::new())) as SSHManagerState)
         .invoke_handler(tauri::generate_handler![
             platform,
             commands::ssh_commands::create_ssh_connection,
             commands::ssh_commands::connect_ssh,
+            commands::ssh_commands::respond_host_key_prompt,
+            commands::ssh_commands::submit_key_passphrase,
+            commands::ssh_commands::submit_keyboard_interactive_responses,
             commands::ssh_commands::disconnect_ssh,
             commands::ssh_commands::remove_ssh_connection,
             commands::ssh_commands::get_connection_state,
             commands::ssh_commands::list_connection_states,
-            commands::ssh_commands::create_ssh_channel,
-            commands::ssh_commands::request_pty,
-            commands::ssh_commands::request_shell,
-            commands::ssh_commands::send_terminal_input,
-            commands::ssh_commands::resize_terminal,
-            commands::ssh_commands::close_ssh_channel,
-            commands::ssh_commands::list_ssh_connections
+            commands::ssh_commands::list_ssh_connections,
+            commands::ssh_commands::import_ssh_config,
+            commands::terminal_commands::terminal_api_version,
+            commands::terminal_commands::create_terminal_session,
+            commands::terminal_commands::send_terminal_input_to_session,
+            commands::terminal_commands::resize_terminal_session,
+            commands::terminal_commands::close_terminal_session,
+            commands::terminal_commands::get_terminal_session,
+            commands::terminal_commands::list_terminal_sessions,
+            commands::terminal_commands::replay_terminal_session,
+            commands::terminal_commands::create_broadcast_group,
+            commands::terminal_commands::join_broadcast_group,
+            commands::terminal_commands::leave_broadcast_group,
+            commands::terminal_commands::delete_broadcast_group,
+            commands::terminal_commands::list_broadcast_groups,
+            commands::terminal_commands::send_terminal_input_to_group,
+            commands::forward_commands::create_port_forward,
+            commands::forward_commands::create_remote_port_forward,
+            commands::forward_commands::create_dynamic_port_forward,
+            commands::forward_commands::list_port_forwards,
+            commands::forward_commands::stop_port_forward,
+            commands::exec_commands::exec_command,
+            commands::exec_commands::run_batch,
+            commands::host_commands::list_hosts,
+            commands::host_commands::create_host,
+            commands::host_commands::update_host,
+            commands::host_commands::delete_host
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
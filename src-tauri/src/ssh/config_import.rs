@@ -0,0 +1,953 @@
+use crate::ssh::error::{SSHError, SSHResult};
+use crate::ssh::types::{AuthMethod, SSHConnectionConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Nesting limit for `Include`, matching OpenSSH
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A directive the importer skipped or could not fully honour
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SSHConfigWarning {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+/// Connections read from an OpenSSH client configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SSHConfigImport {
+    /// One connection per concrete `Host` alias, plus any jump hosts that are not aliases
+    /// themselves. Jump hosts always come before the connections that use them.
+    pub connections: Vec<SSHConnectionConfig>,
+    pub warnings: Vec<SSHConfigWarning>,
+}
+
+/// Returns the user's default OpenSSH client configuration (`~/.ssh/config`)
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+/// Reads an OpenSSH client configuration, following `Include`s, and produces a connection
+/// for every concrete `Host` alias as `ssh -G <alias>` would resolve it
+pub fn import_ssh_config(path: &Path) -> SSHResult<SSHConfigImport> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| SSHError::io("read", path, e))?;
+
+    let mut parser = Parser {
+        home: dirs::home_dir().unwrap_or_default(),
+        warnings: Vec::new(),
+    };
+    let items = parser.parse(&contents, path, 0);
+
+    let mut importer = Importer {
+        items: &items,
+        local_user: local_user(),
+        home: parser.home.clone(),
+        warnings: parser.warnings,
+        hosts: Vec::new(),
+        ids: HashMap::new(),
+        jump_chains: HashMap::new(),
+        extra_jump_hosts: HashMap::new(),
+        connections: Vec::new(),
+    };
+    importer.run();
+
+    Ok(SSHConfigImport {
+        connections: importer.connections,
+        warnings: importer.warnings,
+    })
+}
+
+fn local_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+/// Where a directive came from
+#[derive(Debug, Clone)]
+struct Location {
+    file: String,
+    line: usize,
+}
+
+impl Location {
+    fn warning(&self, message: String) -> SSHConfigWarning {
+        SSHConfigWarning {
+            file: self.file.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Keyword {
+    HostName,
+    Port,
+    User,
+    IdentityFile,
+    ProxyJump,
+    ProxyCommand,
+}
+
+impl Keyword {
+    fn parse(keyword: &str) -> Option<Self> {
+        match keyword {
+            "hostname" => Some(Keyword::HostName),
+            "port" => Some(Keyword::Port),
+            "user" => Some(Keyword::User),
+            "identityfile" => Some(Keyword::IdentityFile),
+            "proxyjump" => Some(Keyword::ProxyJump),
+            "proxycommand" => Some(Keyword::ProxyCommand),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Setting {
+    keyword: Keyword,
+    value: String,
+    location: Location,
+}
+
+#[derive(Debug)]
+enum Criterion {
+    All,
+    Host(Vec<String>),
+    OriginalHost(Vec<String>),
+    User(Vec<String>),
+    /// A criterion the importer can't evaluate; it never matches
+    Unsupported,
+}
+
+#[derive(Debug)]
+enum Item {
+    Host(Vec<String>),
+    Match(Vec<Criterion>),
+    Setting(Setting),
+    /// The contents of an included file, evaluated in the including block's context
+    Include(Vec<Item>),
+}
+
+struct Parser {
+    home: PathBuf,
+    warnings: Vec<SSHConfigWarning>,
+}
+
+impl Parser {
+    fn parse(&mut self, contents: &str, file: &Path, depth: usize) -> Vec<Item> {
+        let mut items = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let location = Location {
+                file: file.display().to_string(),
+                line: index + 1,
+            };
+            let Some((keyword, args)) = split_line(line) else {
+                continue;
+            };
+
+            match keyword.as_str() {
+                "host" => {
+                    if args.is_empty() {
+                        self.warnings.push(location.warning("Host requires at least one pattern".to_string()));
+                    }
+                    items.push(Item::Host(args));
+                }
+                "match" => items.push(Item::Match(self.parse_match(&args, &location))),
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        self.warnings.push(location.warning("Include nested too deeply".to_string()));
+                        continue;
+                    }
+                    for pattern in &args {
+                        for path in self.expand_include(pattern, file, &location) {
+                            match std::fs::read_to_string(&path) {
+                                Ok(contents) => {
+                                    let included = self.parse(&contents, &path, depth + 1);
+                                    items.push(Item::Include(included));
+                                }
+                                Err(e) => self.warnings.push(
+                                    location.warning(format!("Failed to read {}: {}", path.display(), e)),
+                                ),
+                            }
+                        }
+                    }
+                }
+                other => match Keyword::parse(other) {
+                    Some(_) if args.is_empty() => {
+                        self.warnings.push(location.warning(format!("{} requires a value", keyword)));
+                    }
+                    Some(parsed) => {
+                        // ProxyCommand is a shell command line; everything else takes one argument
+                        let value = if parsed == Keyword::ProxyCommand {
+                            args.join(" ")
+                        } else {
+                            args[0].clone()
+                        };
+                        items.push(Item::Setting(Setting {
+                            keyword: parsed,
+                            value,
+                            location,
+                        }));
+                    }
+                    None => {
+                        let name = line.trim_start().split(|c: char| c.is_whitespace() || c == '=').next().unwrap_or("");
+                        self.warnings.push(location.warning(format!("Unsupported directive {} ignored", name)));
+                    }
+                },
+            }
+        }
+
+        items
+    }
+
+    fn parse_match(&mut self, args: &[String], location: &Location) -> Vec<Criterion> {
+        let mut criteria = Vec::new();
+        let mut args = args.iter();
+
+        while let Some(criterion) = args.next() {
+            let lowered = criterion.to_ascii_lowercase();
+            let parsed = match lowered.as_str() {
+                "all" => Criterion::All,
+                // Both only change when other criteria are evaluated, which is all we support
+                "canonical" | "final" => continue,
+                "host" | "originalhost" | "user" => {
+                    let Some(patterns) = args.next() else {
+                        self.warnings.push(location.warning(format!("Match {} requires an argument", lowered)));
+                        criteria.push(Criterion::Unsupported);
+                        break;
+                    };
+                    let patterns = patterns.split(',').map(str::to_string).collect();
+                    match lowered.as_str() {
+                        "host" => Criterion::Host(patterns),
+                        "originalhost" => Criterion::OriginalHost(patterns),
+                        _ => Criterion::User(patterns),
+                    }
+                }
+                _ => {
+                    // exec, localuser, localnetwork and tagged take an argument we can't evaluate
+                    if matches!(lowered.as_str(), "exec" | "localuser" | "localnetwork" | "tagged") {
+                        args.next();
+                    }
+                    self.warnings.push(location.warning(format!(
+                        "Match {} is not supported; the block will not apply",
+                        criterion
+                    )));
+                    Criterion::Unsupported
+                }
+            };
+            criteria.push(parsed);
+        }
+
+        criteria
+    }
+
+    /// Expands an Include argument to the files it names. Relative paths are resolved
+    /// against `~/.ssh`, and `*`/`?` wildcards are allowed in the file name.
+    fn expand_include(&mut self, pattern: &str, including_file: &Path, location: &Location) -> Vec<PathBuf> {
+        let expanded = expand_tilde(pattern, &self.home);
+        let path = if expanded.is_absolute() {
+            expanded
+        } else {
+            let ssh_dir = including_file
+                .parent()
+                .filter(|dir| dir.ends_with(".ssh"))
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| self.home.join(".ssh"));
+            ssh_dir.join(expanded)
+        };
+
+        let file_pattern = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        if !file_pattern.contains(['*', '?']) {
+            return if path.exists() { vec![path] } else { Vec::new() };
+        }
+
+        let Some(dir) = path.parent() else {
+            return Vec::new();
+        };
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut matched: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .filter(|entry| wildcard_match(&file_pattern, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        matched.sort();
+
+        if matched.len() > 64 {
+            self.warnings.push(location.warning(format!("Include {} matched too many files", pattern)));
+            matched.truncate(64);
+        }
+        matched
+    }
+}
+
+/// Splits a configuration line into its lowercased keyword and arguments
+fn split_line(line: &str) -> Option<(String, Vec<String>)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let keyword = line[..end].to_ascii_lowercase();
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    Some((keyword, split_args(rest)))
+}
+
+/// Splits arguments on whitespace, keeping double-quoted arguments together
+fn split_args(args: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut started = false;
+
+    for c in args.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                started = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if started {
+                    split.push(std::mem::take(&mut current));
+                    started = false;
+                }
+            }
+            c => {
+                current.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        split.push(current);
+    }
+    split
+}
+
+/// Matches OpenSSH `*` and `?` wildcards, case-insensitively
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches a pattern list: at least one pattern must match and no negated (`!`) pattern may
+fn matches_pattern_list<S: AsRef<str>>(text: &str, patterns: &[S]) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, text) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern, text),
+        }
+    }
+    matched
+}
+
+fn is_concrete_alias(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '!'])
+}
+
+fn expand_tilde(path: &str, home: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home.join(rest),
+        None if path == "~" => home.to_path_buf(),
+        None => PathBuf::from(path),
+    }
+}
+
+/// The settings in effect for one alias; the first value seen for a keyword wins,
+/// except that every IdentityFile is kept, in order, as OpenSSH tries each of them
+#[derive(Debug, Default)]
+struct HostSettings {
+    settings: Vec<Setting>,
+}
+
+impl HostSettings {
+    fn get(&self, keyword: Keyword) -> Option<&Setting> {
+        self.settings.iter().find(|setting| setting.keyword == keyword)
+    }
+
+    fn get_all(&self, keyword: Keyword) -> impl Iterator<Item = &Setting> {
+        self.settings.iter().filter(move |setting| setting.keyword == keyword)
+    }
+
+    fn set(&mut self, setting: &Setting) {
+        let repeatable = setting.keyword == Keyword::IdentityFile
+            && !self.get_all(setting.keyword).any(|existing| existing.value == setting.value);
+        if repeatable || self.get(setting.keyword).is_none() {
+            self.settings.push(setting.clone());
+        }
+    }
+}
+
+/// A jump host as written in ProxyJump: `[user@]host[:port]`
+#[derive(Debug, Clone, PartialEq)]
+struct JumpSpec {
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+}
+
+impl JumpSpec {
+    fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.strip_prefix("ssh://").unwrap_or(spec);
+        let (user, host_port) = match spec.rsplit_once('@') {
+            Some((user, rest)) => (Some(user.to_string()), rest),
+            None => (None, spec),
+        };
+
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, rest) = bracketed.split_once(']')?;
+            let port = match rest.strip_prefix(':') {
+                Some(port) => Some(port.parse().ok()?),
+                None => None,
+            };
+            (host.to_string(), port)
+        } else {
+            match host_port.rsplit_once(':') {
+                Some((host, port)) if !host.contains(':') => (host.to_string(), Some(port.parse().ok()?)),
+                _ => (host_port.to_string(), None),
+            }
+        };
+
+        (!host.is_empty()).then_some(Self { user, host, port })
+    }
+}
+
+/// Recognises the `ssh -W %h:%p bastion` idiom, which is ProxyJump spelled as a ProxyCommand
+fn proxy_command_jump(command: &str) -> Option<String> {
+    let args = split_args(command);
+    let (program, args) = args.split_first()?;
+    if program != "ssh" && !program.ends_with("/ssh") {
+        return None;
+    }
+
+    let mut forwards_stdio = false;
+    let mut destination = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-W" => forwards_stdio = args.next().is_some_and(|target| target == "%h:%p"),
+            "-q" | "-T" | "-A" | "-C" => {}
+            "-p" | "-l" | "-i" | "-J" | "-F" | "-o" => return None,
+            other if other.starts_with('-') => return None,
+            other if destination.is_none() => destination = Some(other.to_string()),
+            _ => return None,
+        }
+    }
+
+    destination.filter(|_| forwards_stdio)
+}
+
+struct Importer<'a> {
+    items: &'a [Item],
+    local_user: String,
+    home: PathBuf,
+    warnings: Vec<SSHConfigWarning>,
+    /// Concrete aliases in the order they first appear, with their resolved settings
+    hosts: Vec<(String, HostSettings)>,
+    ids: HashMap<String, String>,
+    /// Flattened jump chains (connection ids, outermost first), once resolved
+    jump_chains: HashMap<String, Vec<String>>,
+    /// Jump hosts that aren't aliases, keyed by their ProxyJump spelling
+    extra_jump_hosts: HashMap<String, String>,
+    connections: Vec<SSHConnectionConfig>,
+}
+
+impl Importer<'_> {
+    fn run(&mut self) {
+        let mut aliases = Vec::new();
+        collect_aliases(self.items, &mut aliases);
+
+        for alias in aliases {
+            let mut settings = HostSettings::default();
+            let mut active = true;
+            self.evaluate(self.items, &alias, &mut settings, &mut active);
+            self.ids.insert(alias.clone(), uuid::Uuid::new_v4().to_string());
+            self.hosts.push((alias, settings));
+        }
+
+        for index in 0..self.hosts.len() {
+            let alias = self.hosts[index].0.clone();
+            self.emit(&alias, &mut Vec::new());
+        }
+    }
+
+    fn evaluate(&self, items: &[Item], alias: &str, settings: &mut HostSettings, active: &mut bool) {
+        for item in items {
+            match item {
+                Item::Host(patterns) => *active = matches_pattern_list(alias, patterns),
+                Item::Match(criteria) => {
+                    *active = criteria.iter().all(|criterion| self.criterion_matches(criterion, alias, settings));
+                }
+                Item::Setting(setting) => {
+                    if *active {
+                        settings.set(setting);
+                    }
+                }
+                Item::Include(included) => {
+                    // An included file can't change which block the including file is in
+                    let mut included_active = *active;
+                    self.evaluate(included, alias, settings, &mut included_active);
+                }
+            }
+        }
+    }
+
+    fn criterion_matches(&self, criterion: &Criterion, alias: &str, settings: &HostSettings) -> bool {
+        match criterion {
+            Criterion::All => true,
+            Criterion::Host(patterns) => {
+                let hostname = settings
+                    .get(Keyword::HostName)
+                    .map(|setting| setting.value.replace("%h", alias))
+                    .unwrap_or_else(|| alias.to_string());
+                matches_pattern_list(&hostname, patterns)
+            }
+            Criterion::OriginalHost(patterns) => matches_pattern_list(alias, patterns),
+            Criterion::User(patterns) => {
+                let user = settings.get(Keyword::User).map(|setting| setting.value.as_str()).unwrap_or(&self.local_user);
+                matches_pattern_list(user, patterns)
+            }
+            Criterion::Unsupported => false,
+        }
+    }
+
+    /// Adds the connection for an alias after the jump hosts it needs, returning its
+    /// flattened jump chain. `visiting` holds the aliases being resolved, to catch loops.
+    fn emit(&mut self, alias: &str, visiting: &mut Vec<String>) -> Option<Vec<String>> {
+        if let Some(chain) = self.jump_chains.get(alias) {
+            return Some(chain.clone());
+        }
+        if visiting.iter().any(|visited| visited == alias) {
+            return None;
+        }
+        visiting.push(alias.to_string());
+
+        let index = self.hosts.iter().position(|(host, _)| host == alias)?;
+        let jump_setting = {
+            let settings = &self.hosts[index].1;
+            settings
+                .get(Keyword::ProxyJump)
+                .cloned()
+                .or_else(|| settings.get(Keyword::ProxyCommand).cloned())
+        };
+
+        let mut chain = Vec::new();
+        if let Some(setting) = jump_setting {
+            match self.resolve_jumps(alias, &setting, visiting) {
+                Some(resolved) => chain = resolved,
+                None => self.warnings.push(setting.location.warning(format!(
+                    "Jump hosts for {} form a loop; it will connect directly",
+                    alias
+                ))),
+            }
+        }
+
+        visiting.pop();
+        let config = self.build_connection(alias, chain.clone());
+        self.connections.push(config);
+        self.jump_chains.insert(alias.to_string(), chain.clone());
+        Some(chain)
+    }
+
+    /// Resolves a ProxyJump (or equivalent ProxyCommand) setting to a flattened chain of ids
+    fn resolve_jumps(&mut self, alias: &str, setting: &Setting, visiting: &mut Vec<String>) -> Option<Vec<String>> {
+        let specs = match setting.keyword {
+            Keyword::ProxyJump if setting.value.eq_ignore_ascii_case("none") => return Some(Vec::new()),
+            Keyword::ProxyJump => setting.value.split(',').map(str::to_string).collect::<Vec<_>>(),
+            _ if setting.value.eq_ignore_ascii_case("none") => return Some(Vec::new()),
+            _ => match proxy_command_jump(&setting.value) {
+                Some(jump) => vec![jump],
+                None => {
+                    self.warnings.push(setting.location.warning(format!(
+                        "ProxyCommand for {} is not supported; it will connect directly",
+                        alias
+                    )));
+                    return Some(Vec::new());
+                }
+            },
+        };
+
+        let mut chain = Vec::new();
+        for spec in specs {
+            let Some(jump) = JumpSpec::parse(&spec) else {
+                self.warnings.push(setting.location.warning(format!("Invalid jump host {}", spec)));
+                continue;
+            };
+
+            let is_alias = jump.user.is_none() && jump.port.is_none() && self.ids.contains_key(&jump.host);
+            if is_alias {
+                // The jump host's own jump hosts come first, as OpenSSH chains them
+                chain.extend(self.emit(&jump.host, visiting)?);
+                chain.push(self.ids[&jump.host].clone());
+            } else {
+                chain.push(self.extra_jump_host(&spec, jump));
+            }
+        }
+        Some(chain)
+    }
+
+    /// Adds a connection for a jump host that has no alias of its own
+    fn extra_jump_host(&mut self, spec: &str, jump: JumpSpec) -> String {
+        if let Some(id) = self.extra_jump_hosts.get(spec) {
+            return id.clone();
+        }
+
+        let config = SSHConnectionConfig {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: spec.to_string(),
+            hostname: jump.host,
+            port: jump.port.unwrap_or(22),
+            username: jump.user.unwrap_or_else(|| self.local_user.clone()),
+            auth_methods: vec![AuthMethod::Agent, AuthMethod::KeyboardInteractive],
+            ..Default::default()
+        };
+
+        let id = config.id.clone();
+        self.extra_jump_hosts.insert(spec.to_string(), id.clone());
+        self.connections.push(config);
+        id
+    }
+
+    fn build_connection(&mut self, alias: &str, jump_hosts: Vec<String>) -> SSHConnectionConfig {
+        let settings = &self.hosts.iter().find(|(host, _)| host == alias).expect("alias was collected").1;
+        let value = |keyword| settings.get(keyword).map(|setting: &Setting| setting.value.clone());
+
+        let hostname = value(Keyword::HostName)
+            .map(|hostname| hostname.replace("%h", alias).replace("%%", "%"))
+            .unwrap_or_else(|| alias.to_string());
+        let username = value(Keyword::User).unwrap_or_else(|| self.local_user.clone());
+
+        let mut warnings = Vec::new();
+        let port = match settings.get(Keyword::Port) {
+            Some(setting) => setting.value.parse().unwrap_or_else(|_| {
+                warnings.push(setting.location.warning(format!("Invalid port {} for {}; using 22", setting.value, alias)));
+                22
+            }),
+            None => 22,
+        };
+
+        // Keys are tried first when one exists, then the agent and interactive prompts.
+        // A connection holds a single key, so the first one that exists is used.
+        let mut auth_methods = Vec::new();
+        let mut private_key_path = None;
+        for setting in settings.get_all(Keyword::IdentityFile) {
+            let path = setting.value
+                .replace("%d", &self.home.to_string_lossy())
+                .replace("%u", &self.local_user)
+                .replace("%h", &hostname)
+                .replace("%r", &username)
+                .replace("%%", "%");
+            let path = expand_tilde(&path, &self.home);
+            if !path.exists() {
+                warnings.push(setting.location.warning(format!(
+                    "IdentityFile {} for {} does not exist",
+                    path.display(),
+                    alias
+                )));
+            } else if private_key_path.is_none() {
+                auth_methods.push(AuthMethod::PublicKey);
+                private_key_path = Some(path.to_string_lossy().to_string());
+            } else {
+                warnings.push(setting.location.warning(format!(
+                    "IdentityFile {} for {} ignored; only one key per host is imported",
+                    path.display(),
+                    alias
+                )));
+            }
+        }
+        auth_methods.extend([AuthMethod::Agent, AuthMethod::KeyboardInteractive]);
+
+        self.warnings.extend(warnings);
+        SSHConnectionConfig {
+            id: self.ids[alias].clone(),
+            name: alias.to_string(),
+            hostname,
+            port,
+            username,
+            auth_methods,
+            private_key_path,
+            jump_hosts,
+            ..Default::default()
+        }
+    }
+}
+
+fn collect_aliases(items: &[Item], aliases: &mut Vec<String>) {
+    for item in items {
+        match item {
+            Item::Host(patterns) => {
+                for pattern in patterns {
+                    if is_concrete_alias(pattern) && !aliases.contains(pattern) {
+                        aliases.push(pattern.clone());
+                    }
+                }
+            }
+            Item::Include(included) => collect_aliases(included, aliases),
+            Item::Match(_) | Item::Setting(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Writes `files` (relative name, contents) into a fresh directory and imports the first one
+    fn import(files: &[(&str, &str)]) -> SSHConfigImport {
+        let dir = std::env::temp_dir()
+            .join(format!("hana-ssh-config-{}", uuid::Uuid::new_v4()))
+            .join(".ssh");
+        for (name, contents) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        import_ssh_config(&dir.join(files[0].0)).unwrap()
+    }
+
+    fn connection<'a>(import: &'a SSHConfigImport, name: &str) -> &'a SSHConnectionConfig {
+        import.connections.iter().find(|c| c.name == name).unwrap_or_else(|| panic!("no connection {}", name))
+    }
+
+    #[test]
+    fn test_host_blocks_first_value_wins() {
+        let import = import(&[(
+            "config",
+            "Host web\n  HostName web.example.com\n  Port 2222\n  User deploy\n\n\
+             Host *\n  User fallback\n  Port 22\n",
+        )]);
+
+        assert_eq!(import.connections.len(), 1);
+        let web = connection(&import, "web");
+        assert_eq!(web.hostname, "web.example.com");
+        assert_eq!(web.port, 2222);
+        assert_eq!(web.username, "deploy");
+        assert!(import.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_wildcard_and_negated_patterns() {
+        let import = import(&[(
+            "config",
+            "Host db1 db2 *.internal\n\
+             Host db*\n  User dba\n\
+             Host * !db2\n  Port=2200\n",
+        )]);
+
+        let names: Vec<_> = import.connections.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["db1", "db2"]);
+        assert_eq!(connection(&import, "db1").port, 2200);
+        assert_eq!(connection(&import, "db2").port, 22);
+        assert_eq!(connection(&import, "db2").username, "dba");
+    }
+
+    #[test]
+    fn test_match_host_and_user() {
+        let import = import(&[(
+            "config",
+            "Host app\n  HostName app.prod.example.com\n  User root\n\
+             Host cache\n  HostName cache.dev.example.com\n\
+             Match host *.prod.example.com user root\n  Port 2022\n\
+             Match originalhost cache\n  User redis\n\
+             Match exec \"test -f /nope\"\n  Port 9999\n",
+        )]);
+
+        assert_eq!(connection(&import, "app").port, 2022);
+        assert_eq!(connection(&import, "cache").port, 22);
+        assert_eq!(connection(&import, "cache").username, "redis");
+        assert!(import.warnings.iter().any(|w| w.line == 10 && w.message.contains("Match exec")));
+    }
+
+    #[test]
+    fn test_include_relative_glob_and_block_context() {
+        let import = import(&[
+            ("config", "Include conf.d/*.conf\nHost main\n  Include extra\n"),
+            ("conf.d/10-a.conf", "Host a\n  HostName a.example.com\n"),
+            ("conf.d/20-b.conf", "Host b\n  Port 2200\n"),
+            ("conf.d/ignored.txt", "Host ignored\n"),
+            ("extra", "User included\n"),
+        ]);
+
+        let names: Vec<_> = import.connections.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "main"]);
+        assert_eq!(connection(&import, "a").hostname, "a.example.com");
+        assert_eq!(connection(&import, "b").port, 2200);
+        // The include inside `Host main` only applies to main
+        assert_eq!(connection(&import, "main").username, "included");
+        assert_ne!(connection(&import, "a").username, "included");
+    }
+
+    #[test]
+    fn test_identity_file() {
+        let key = format!("{}/tests/fixtures/keys/id_ed25519", env!("CARGO_MANIFEST_DIR"));
+        let import = import(&[(
+            "config",
+            &format!("Host keyed\n  IdentityFile \"{}\"\nHost missing\n  IdentityFile ~/.ssh/nope\n", key),
+        )]);
+
+        let keyed = connection(&import, "keyed");
+        assert_eq!(keyed.private_key_path.as_deref(), Some(key.as_str()));
+        assert_eq!(
+            keyed.auth_methods,
+            vec![AuthMethod::PublicKey, AuthMethod::Agent, AuthMethod::KeyboardInteractive]
+        );
+
+        let missing = connection(&import, "missing");
+        assert_eq!(missing.private_key_path, None);
+        assert_eq!(missing.auth_methods, vec![AuthMethod::Agent, AuthMethod::KeyboardInteractive]);
+        assert!(import.warnings.iter().any(|w| w.line == 4 && w.message.contains("does not exist")));
+    }
+
+    #[test]
+    fn test_every_identity_file_is_considered() {
+        let key = format!("{}/tests/fixtures/keys/id_ed25519", env!("CARGO_MANIFEST_DIR"));
+        let other = format!("{}/tests/fixtures/keys/id_ed25519_encrypted", env!("CARGO_MANIFEST_DIR"));
+        let import = import(&[(
+            "config",
+            &format!(
+                "Host keyed\n  IdentityFile ~/.ssh/nope\n  IdentityFile \"{}\"\nHost *\n  IdentityFile \"{}\"\n",
+                key, other
+            ),
+        )]);
+
+        // The missing key is skipped, the first existing one is used and the rest are reported
+        let keyed = connection(&import, "keyed");
+        assert_eq!(keyed.private_key_path.as_deref(), Some(key.as_str()));
+        assert!(import.warnings.iter().any(|w| w.line == 2 && w.message.contains("does not exist")));
+        assert!(import.warnings.iter().any(|w| w.line == 5 && w.message.contains("ignored") && w.message.contains("id_ed25519_encrypted")));
+    }
+
+    #[test]
+    fn test_proxy_jump_chains_are_flattened_and_ordered() {
+        let import = import(&[(
+            "config",
+            "Host target\n  ProxyJump inner\n\
+             Host inner\n  ProxyJump outer\n\
+             Host outer\n  HostName bastion.example.com\n\
+             Host other\n  ProxyJump admin@jump.example.com:2222,outer\n",
+        )]);
+
+        let names: Vec<_> = import.connections.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["outer", "inner", "target", "admin@jump.example.com:2222", "other"]);
+
+        let id = |name| connection(&import, name).id.clone();
+        assert_eq!(connection(&import, "target").jump_hosts, vec![id("outer"), id("inner")]);
+        assert_eq!(
+            connection(&import, "other").jump_hosts,
+            vec![id("admin@jump.example.com:2222"), id("outer")]
+        );
+
+        let extra = connection(&import, "admin@jump.example.com:2222");
+        assert_eq!(extra.hostname, "jump.example.com");
+        assert_eq!(extra.port, 2222);
+        assert_eq!(extra.username, "admin");
+    }
+
+    #[test]
+    fn test_proxy_jump_loop_is_reported() {
+        let import = import(&[("config", "Host a\n  ProxyJump b\nHost b\n  ProxyJump a\n")]);
+
+        assert_eq!(import.connections.len(), 2);
+        assert!(import.warnings.iter().any(|w| w.message.contains("form a loop")));
+    }
+
+    #[test]
+    fn test_proxy_command() {
+        let import = import(&[(
+            "config",
+            "Host bastion\n\
+             Host via-w\n  ProxyCommand ssh -q -W %h:%p bastion\n\
+             Host via-nc\n  ProxyCommand nc -X 5 -x proxy:1080 %h %p\n",
+        )]);
+
+        let bastion = connection(&import, "bastion").id.clone();
+        assert_eq!(connection(&import, "via-w").jump_hosts, vec![bastion]);
+        assert!(connection(&import, "via-nc").jump_hosts.is_empty());
+        assert!(import.warnings.iter().any(|w| w.line == 5 && w.message.contains("ProxyCommand")));
+    }
+
+    #[test]
+    fn test_unknown_directives_are_warned() {
+        let import = import(&[(
+            "config",
+            "# comment\nForwardAgent yes\nHost x\n  ServerAliveInterval 30\n  Port nope\n",
+        )]);
+
+        let messages: Vec<_> = import.warnings.iter().map(|w| (w.line, w.message.as_str())).collect();
+        assert!(messages.contains(&(2, "Unsupported directive ForwardAgent ignored")));
+        assert!(messages.contains(&(4, "Unsupported directive ServerAliveInterval ignored")));
+        assert!(messages.iter().any(|(line, message)| *line == 5 && message.contains("Invalid port")));
+        assert_eq!(connection(&import, "x").port, 22);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let result = import_ssh_config(Path::new("/nonexistent/hana/ssh_config"));
+        assert!(matches!(result, Err(SSHError::Io { ref operation, .. }) if operation == "read"));
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*.example.com", "web.EXAMPLE.com"));
+        assert!(wildcard_match("db?", "db1"));
+        assert!(!wildcard_match("db?", "db10"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(!wildcard_match("a*b*c", "axxbyy"));
+    }
+
+    #[test]
+    fn test_jump_spec_parse() {
+        assert_eq!(
+            JumpSpec::parse("user@host:2200"),
+            Some(JumpSpec { user: Some("user".to_string()), host: "host".to_string(), port: Some(2200) })
+        );
+        assert_eq!(
+            JumpSpec::parse("[2001:db8::1]:22"),
+            Some(JumpSpec { user: None, host: "2001:db8::1".to_string(), port: Some(22) })
+        );
+        assert_eq!(JumpSpec::parse("host").unwrap().port, None);
+        assert_eq!(JumpSpec::parse("host:notaport"), None);
+    }
+}
@@ -0,0 +1,257 @@
+use crate::ssh::error::{SSHError, SSHResult};
+use keyring::credential::CredentialBuilder;
+use keyring::Entry;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Keyring service under which all connection secrets are stored
+pub const KEYRING_SERVICE: &str = "cc.mcjk.hana";
+
+/// The kinds of secret a connection can keep in the keyring
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum SecretKind {
+    Password,
+    KeyPassphrase,
+}
+
+impl SecretKind {
+    fn account_suffix(&self) -> &'static str {
+        match self {
+            SecretKind::Password => "password",
+            SecretKind::KeyPassphrase => "key-passphrase",
+        }
+    }
+}
+
+/// Points at a secret held in the platform keyring; never contains the secret itself
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CredentialRef {
+    pub service: String,
+    pub account: String,
+}
+
+impl CredentialRef {
+    /// Returns the reference for a connection's secret of the given kind
+    pub fn for_connection(connection_id: &str, kind: SecretKind) -> Self {
+        Self {
+            service: KEYRING_SERVICE.to_string(),
+            account: format!("{}:{}", connection_id, kind.account_suffix()),
+        }
+    }
+}
+
+/// Reads and writes connection secrets in the platform secret store
+/// (Keychain, Credential Manager or the Secret Service)
+#[derive(Clone, Default)]
+pub struct CredentialStore {
+    builder: Option<Arc<CredentialBuilder>>,
+    /// Prefixed to the accounts this store saves to; a namespaced store only deletes its own
+    namespace: Option<Arc<str>>,
+}
+
+impl CredentialStore {
+    /// Uses the platform's default keyring
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// Uses the given keyring backend instead of the platform default
+    pub fn with_builder(builder: Box<CredentialBuilder>) -> Self {
+        Self {
+            builder: Some(Arc::from(builder)),
+            namespace: None,
+        }
+    }
+
+    /// Returns a store on the same keyring that saves under `<namespace>:<id>:<kind>`,
+    /// so its secrets never share an account with another owner's
+    pub fn namespaced(&self, namespace: &str) -> Self {
+        Self {
+            builder: self.builder.clone(),
+            namespace: Some(Arc::from(namespace)),
+        }
+    }
+
+    /// Whether this store saved the secret a reference points at. A store without a
+    /// namespace owns every reference.
+    pub fn owns(&self, credential: &CredentialRef) -> bool {
+        match &self.namespace {
+            Some(namespace) => credential.service == KEYRING_SERVICE
+                && credential.account.strip_prefix(&**namespace).is_some_and(|rest| rest.starts_with(':')),
+            None => true,
+        }
+    }
+
+    fn entry(&self, credential: &CredentialRef) -> SSHResult<Entry> {
+        match &self.builder {
+            Some(builder) => builder
+                .build(None, &credential.service, &credential.account)
+                .map(Entry::new_with_credential),
+            None => Entry::new(&credential.service, &credential.account),
+        }
+        .map_err(|e| keyring_error(credential, "Failed to open the entry", e))
+    }
+
+    /// Saves a connection secret, replacing any previous value, and returns its reference
+    pub fn store(&self, connection_id: &str, kind: SecretKind, secret: &str) -> SSHResult<CredentialRef> {
+        let credential = match &self.namespace {
+            Some(namespace) => CredentialRef::for_connection(&format!("{}:{}", namespace, connection_id), kind),
+            None => CredentialRef::for_connection(connection_id, kind),
+        };
+        self.restore(&credential, secret)?;
+        Ok(credential)
+    }
+
+    /// Writes a secret back to the entry a reference points at, replacing any value it has
+    pub fn restore(&self, credential: &CredentialRef, secret: &str) -> SSHResult<()> {
+        self.entry(credential)?
+            .set_password(secret)
+            .map_err(|e| keyring_error(credential, "Failed to save the secret", e))
+    }
+
+    /// Reads the secret a reference points at, or `None` if the keyring has no such entry
+    pub fn load(&self, credential: &CredentialRef) -> SSHResult<Option<String>> {
+        match self.entry(credential)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(keyring_error(credential, "Failed to read the secret", e)),
+        }
+    }
+
+    /// Removes the secret a reference points at; a missing entry is not an error
+    pub fn delete(&self, credential: &CredentialRef) -> SSHResult<()> {
+        match self.entry(credential)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(keyring_error(credential, "Failed to remove the secret", e)),
+        }
+    }
+}
+
+fn keyring_error(credential: &CredentialRef, action: &str, error: keyring::Error) -> SSHError {
+    SSHError::Credential {
+        account: credential.account.clone(),
+        reason: format!("{}: {}", action, error),
+    }
+}
+
+#[cfg(test)]
+mod memory {
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi, CredentialPersistence};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    type Secrets = Arc<Mutex<HashMap<(String, String), Vec<u8>>>>;
+
+    /// Keyring backend that keeps secrets in memory, shared by every entry it builds.
+    /// Unlike `keyring::mock`, entries built for the same account see each other's writes.
+    #[derive(Debug, Default)]
+    pub struct MemoryBuilder {
+        secrets: Secrets,
+    }
+
+    #[derive(Debug)]
+    struct MemoryCredential {
+        key: (String, String),
+        secrets: Secrets,
+    }
+
+    impl CredentialApi for MemoryCredential {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            self.secrets.lock().unwrap().insert(self.key.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            self.secrets.lock().unwrap().get(&self.key).cloned().ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            self.secrets.lock().unwrap().remove(&self.key).map(|_| ()).ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl CredentialBuilderApi for MemoryBuilder {
+        fn build(&self, _target: Option<&str>, service: &str, user: &str) -> keyring::Result<Box<Credential>> {
+            Ok(Box::new(MemoryCredential {
+                key: (service.to_string(), user.to_string()),
+                secrets: self.secrets.clone(),
+            }))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn persistence(&self) -> CredentialPersistence {
+            CredentialPersistence::ProcessOnly
+        }
+    }
+}
+
+#[cfg(test)]
+impl CredentialStore {
+    /// A store backed by an in-memory keyring, for tests
+    pub(crate) fn in_memory() -> Self {
+        Self::with_builder(Box::new(memory::MemoryBuilder::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_is_keyed_by_connection() {
+        let password = CredentialRef::for_connection("conn-1", SecretKind::Password);
+        let passphrase = CredentialRef::for_connection("conn-1", SecretKind::KeyPassphrase);
+
+        assert_eq!(password.service, KEYRING_SERVICE);
+        assert_eq!(password.account, "conn-1:password");
+        assert_eq!(passphrase.account, "conn-1:key-passphrase");
+    }
+
+    #[test]
+    fn test_store_load_delete_round_trip() {
+        let store = CredentialStore::in_memory();
+
+        let credential = store.store("conn-1", SecretKind::Password, "s3cret").unwrap();
+        assert_eq!(store.load(&credential), Ok(Some("s3cret".to_string())));
+
+        store.store("conn-1", SecretKind::Password, "rotated").unwrap();
+        assert_eq!(store.load(&credential), Ok(Some("rotated".to_string())));
+
+        store.delete(&credential).unwrap();
+        assert_eq!(store.load(&credential), Ok(None));
+        // Deleting twice is harmless
+        assert!(store.delete(&credential).is_ok());
+    }
+
+    #[test]
+    fn test_clones_share_the_backend() {
+        let store = CredentialStore::in_memory();
+        let credential = store.clone().store("conn-1", SecretKind::KeyPassphrase, "hunter2").unwrap();
+
+        assert_eq!(store.load(&credential), Ok(Some("hunter2".to_string())));
+    }
+
+    #[test]
+    fn test_namespaced_store_keeps_its_own_accounts() {
+        let store = CredentialStore::in_memory();
+        let live = store.namespaced("connection");
+
+        let saved = store.store("conn-1", SecretKind::Password, "saved").unwrap();
+        let session = live.store("conn-1", SecretKind::Password, "session").unwrap();
+
+        assert_eq!(session.account, "connection:conn-1:password");
+        assert_eq!(store.load(&saved), Ok(Some("saved".to_string())));
+        assert_eq!(store.load(&session), Ok(Some("session".to_string())));
+        assert!(live.owns(&session));
+        assert!(!live.owns(&saved));
+        assert!(store.owns(&saved));
+    }
+}
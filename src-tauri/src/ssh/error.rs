@@ -0,0 +1,385 @@
+use crate::ssh::types::{AuthReport, ValidationError};
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use serde_json::{json, Value};
+
+/// What a `NotFound` error was looking for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResourceKind {
+    Connection,
+    TerminalSession,
+    Host,
+    JumpHost,
+    PortForward,
+    BroadcastGroup,
+}
+
+impl ResourceKind {
+    fn description(&self) -> &'static str {
+        match self {
+            ResourceKind::Connection => "Connection",
+            ResourceKind::TerminalSession => "Terminal session",
+            ResourceKind::Host => "Host",
+            ResourceKind::JumpHost => "Jump host",
+            ResourceKind::PortForward => "Port forward",
+            ResourceKind::BroadcastGroup => "Broadcast group",
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ResourceKind::Connection => "connection",
+            ResourceKind::TerminalSession => "terminalSession",
+            ResourceKind::Host => "host",
+            ResourceKind::JumpHost => "jumpHost",
+            ResourceKind::PortForward => "portForward",
+            ResourceKind::BroadcastGroup => "broadcastGroup",
+        }
+    }
+}
+
+/// Why an SSH operation failed
+///
+/// Sent to the frontend as `{ code, message, retryable, details }`. `code` is stable
+/// for each variant and safe to branch on; `message` is for people and may change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SSHError {
+    /// The configuration or arguments were rejected before anything was attempted
+    Validation(Vec<ValidationError>),
+    /// The hostname could not be resolved
+    Dns { host: String, reason: String },
+    /// No TCP connection could be made to any of the host's addresses
+    Tcp { host: String, port: u16, reason: String },
+    /// The server didn't answer in time
+    Timeout { operation: String },
+    /// None of the host's addresses answered within the connect timeout. Shares the
+    /// `timeout` code; `errors` says what became of each address tried.
+    ConnectTimeout { host: String, port: u16, timeout_secs: f32, errors: Vec<String> },
+    /// The transport was up but the SSH handshake failed
+    Handshake { reason: String },
+    /// The server presented an unknown key that the user didn't accept
+    HostKeyRejected { host: String, port: u16, key_type: String, fingerprint: String },
+    /// The server presented a different key from the one known_hosts records for it
+    HostKeyChanged {
+        host: String,
+        port: u16,
+        key_type: String,
+        known_fingerprint: String,
+        presented_fingerprint: String,
+        line: usize,
+    },
+    /// Every method in the authentication chain failed
+    Auth(AuthReport),
+    /// A jump host on the way to the target could not be reached
+    JumpHost { name: String, host: String, port: u16, cause: Box<SSHError> },
+    /// A channel couldn't be opened, or a request on it failed or was refused
+    Channel { reason: String },
+    /// Another socket is already listening on the address a port forward wants
+    AddressInUse { address: String, port: u16 },
+    /// A port forward couldn't listen on its address for any other reason
+    Bind { address: String, port: u16, reason: String },
+    /// The server wouldn't listen on the address a remote port forward asked for
+    ForwardRefused { address: String, port: u16 },
+    /// The connection has no active session
+    NotConnected,
+    /// An established session ended without being closed from this side
+    ConnectionLost { reason: String },
+    /// The server stopped answering keepalive probes, so the connection was given up as dead
+    KeepaliveTimeout { interval_secs: u64, missed: u32 },
+    /// No connection, terminal session or host has the given id
+    NotFound { kind: ResourceKind, id: String },
+    /// The operation doesn't make sense in the current state, e.g. answering a prompt
+    /// that isn't pending
+    InvalidState(String),
+    /// The platform keyring refused to read or write a secret
+    Credential { account: String, reason: String },
+    /// A file (known_hosts, a private key, the inventory) couldn't be read or written
+    Io { operation: String, path: String, reason: String },
+    Internal(String),
+}
+
+pub type SSHResult<T> = Result<T, SSHError>;
+
+impl SSHError {
+    pub fn not_found(kind: ResourceKind, id: &str) -> Self {
+        SSHError::NotFound { kind, id: id.to_string() }
+    }
+
+    pub fn channel<E: std::fmt::Display>(context: &str, error: E) -> Self {
+        SSHError::Channel { reason: format!("{}: {}", context, error) }
+    }
+
+    pub fn io<E: std::fmt::Display>(operation: &str, path: &std::path::Path, error: E) -> Self {
+        SSHError::Io {
+            operation: operation.to_string(),
+            path: path.display().to_string(),
+            reason: error.to_string(),
+        }
+    }
+
+    /// Stable identifier of the kind of failure
+    pub fn code(&self) -> &'static str {
+        match self {
+            SSHError::Validation(_) => "validation",
+            SSHError::Dns { .. } => "dns",
+            SSHError::Tcp { .. } => "tcp",
+            SSHError::Timeout { .. } | SSHError::ConnectTimeout { .. } => "timeout",
+            SSHError::Handshake { .. } => "handshake",
+            SSHError::HostKeyRejected { .. } => "host_key_rejected",
+            SSHError::HostKeyChanged { .. } => "host_key_changed",
+            SSHError::Auth(_) => "auth_failed",
+            SSHError::JumpHost { .. } => "jump_host",
+            SSHError::Channel { .. } => "channel",
+            SSHError::AddressInUse { .. } => "address_in_use",
+            SSHError::Bind { .. } => "bind",
+            SSHError::ForwardRefused { .. } => "forward_refused",
+            SSHError::NotConnected => "not_connected",
+            SSHError::ConnectionLost { .. } => "connection_lost",
+            SSHError::KeepaliveTimeout { .. } => "keepalive_timeout",
+            SSHError::NotFound { .. } => "not_found",
+            SSHError::InvalidState(_) => "invalid_state",
+            SSHError::Credential { .. } => "credential",
+            SSHError::Io { .. } => "io",
+            SSHError::Internal(_) => "internal",
+        }
+    }
+
+    /// Whether trying again unchanged might succeed, i.e. the failure was in the network
+    /// or on the server rather than in what was asked for
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SSHError::Dns { .. }
+            | SSHError::Tcp { .. }
+            | SSHError::Timeout { .. }
+            | SSHError::ConnectTimeout { .. }
+            | SSHError::Handshake { .. }
+            | SSHError::ConnectionLost { .. }
+            | SSHError::KeepaliveTimeout { .. } => true,
+            SSHError::JumpHost { cause, .. } => cause.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// The variant's fields, for the frontend
+    fn details(&self) -> Value {
+        match self {
+            SSHError::Validation(errors) => json!({ "errors": errors }),
+            SSHError::Dns { host, reason } => json!({ "host": host, "reason": reason }),
+            SSHError::Tcp { host, port, reason } => json!({ "host": host, "port": port, "reason": reason }),
+            SSHError::Timeout { operation } => json!({ "operation": operation }),
+            SSHError::ConnectTimeout { host, port, timeout_secs, errors } => json!({
+                "host": host,
+                "port": port,
+                "timeoutSecs": timeout_secs,
+                "errors": errors,
+            }),
+            SSHError::Handshake { reason } | SSHError::Channel { reason } | SSHError::ConnectionLost { reason } => {
+                json!({ "reason": reason })
+            }
+            SSHError::HostKeyRejected { host, port, key_type, fingerprint } => json!({
+                "host": host,
+                "port": port,
+                "keyType": key_type,
+                "fingerprint": fingerprint,
+            }),
+            SSHError::HostKeyChanged { host, port, key_type, known_fingerprint, presented_fingerprint, line } => json!({
+                "host": host,
+                "port": port,
+                "keyType": key_type,
+                "knownFingerprint": known_fingerprint,
+                "presentedFingerprint": presented_fingerprint,
+                "line": line,
+            }),
+            SSHError::Auth(report) => json!({ "authReport": report }),
+            SSHError::JumpHost { name, host, port, cause } => json!({
+                "name": name,
+                "host": host,
+                "port": port,
+                "cause": cause,
+            }),
+            SSHError::KeepaliveTimeout { interval_secs, missed } => json!({
+                "intervalSecs": interval_secs,
+                "missed": missed,
+            }),
+            SSHError::AddressInUse { address, port } | SSHError::ForwardRefused { address, port } => {
+                json!({ "address": address, "port": port })
+            }
+            SSHError::Bind { address, port, reason } => json!({ "address": address, "port": port, "reason": reason }),
+            SSHError::NotFound { kind, id } => json!({ "kind": kind.code(), "id": id }),
+            SSHError::Credential { account, reason } => json!({ "account": account, "reason": reason }),
+            SSHError::Io { operation, path, reason } => json!({ "operation": operation, "path": path, "reason": reason }),
+            SSHError::NotConnected | SSHError::InvalidState(_) | SSHError::Internal(_) => Value::Null,
+        }
+    }
+}
+
+impl std::fmt::Display for SSHError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SSHError::Validation(errors) => write!(
+                f,
+                "Configuration validation failed: {}",
+                errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            SSHError::Dns { host, reason } => write!(f, "Failed to resolve {}: {}", host, reason),
+            SSHError::Tcp { host, port, reason } => write!(f, "Failed to connect to {}:{}: {}", host, port, reason),
+            SSHError::Timeout { operation } => write!(f, "Timed out {}", operation),
+            SSHError::ConnectTimeout { host, port, timeout_secs, errors } => {
+                write!(f, "Timed out connecting to {}:{} after {}s", host, port, timeout_secs)?;
+                if !errors.is_empty() {
+                    write!(f, " ({})", errors.join(", "))?;
+                }
+                Ok(())
+            }
+            SSHError::Handshake { reason } => write!(f, "SSH connection failed: {}", reason),
+            SSHError::HostKeyRejected { host, port, key_type, fingerprint } => write!(
+                f,
+                "Host key verification failed: the {} key {} for {}:{} was not accepted",
+                key_type, fingerprint, host, port
+            ),
+            SSHError::HostKeyChanged { host, port, key_type, known_fingerprint, presented_fingerprint, line } => write!(
+                f,
+                "Host key verification failed: the {} key for {}:{} has changed. Expected {} but the server presented {} (known_hosts line {})",
+                key_type, host, port, known_fingerprint, presented_fingerprint, line
+            ),
+            SSHError::Auth(report) => write!(f, "Authentication failed: {}", report.failure_summary()),
+            SSHError::JumpHost { name, host, port, cause } => write!(f, "Jump host {} ({}:{}): {}", name, host, port, cause),
+            SSHError::Channel { reason } => write!(f, "{}", reason),
+            SSHError::AddressInUse { address, port } => write!(f, "{}:{} is already in use", address, port),
+            SSHError::Bind { address, port, reason } => write!(f, "Failed to listen on {}:{}: {}", address, port, reason),
+            SSHError::ForwardRefused { address, port } => {
+                write!(f, "The server refused to listen on {}:{}", address, port)
+            }
+            SSHError::NotConnected => write!(f, "SSH session not available"),
+            SSHError::ConnectionLost { reason } => write!(f, "Connection lost: {}", reason),
+            SSHError::KeepaliveTimeout { interval_secs, missed } => write!(
+                f,
+                "Connection lost: the server did not answer {} keepalive probes sent {}s apart",
+                missed, interval_secs
+            ),
+            SSHError::NotFound { kind, id } => write!(f, "{} {} not found", kind.description(), id),
+            SSHError::InvalidState(message) | SSHError::Internal(message) => write!(f, "{}", message),
+            SSHError::Credential { account, reason } => write!(f, "Keyring entry {}: {}", account, reason),
+            SSHError::Io { operation, path, reason } => write!(f, "Failed to {} {}: {}", operation, path, reason),
+        }
+    }
+}
+
+impl std::error::Error for SSHError {}
+
+impl From<Vec<ValidationError>> for SSHError {
+    fn from(errors: Vec<ValidationError>) -> Self {
+        SSHError::Validation(errors)
+    }
+}
+
+impl From<ValidationError> for SSHError {
+    fn from(error: ValidationError) -> Self {
+        SSHError::Validation(vec![error])
+    }
+}
+
+impl Serialize for SSHError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut error = serializer.serialize_struct("SSHError", 4)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.serialize_field("retryable", &self.is_retryable())?;
+        error.serialize_field("details", &self.details())?;
+        error.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::types::{AuthFailure, AuthMethod};
+
+    #[test]
+    fn test_serializes_code_message_and_details() {
+        let error = SSHError::Tcp {
+            host: "example.com".to_string(),
+            port: 22,
+            reason: "connection refused".to_string(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "code": "tcp",
+                "message": "Failed to connect to example.com:22: connection refused",
+                "retryable": true,
+                "details": { "host": "example.com", "port": 22, "reason": "connection refused" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_connect_timeout_lists_each_address() {
+        let error = SSHError::ConnectTimeout {
+            host: "example.com".to_string(),
+            port: 22,
+            timeout_secs: 15.0,
+            errors: vec![
+                "[2001:db8::1]:22: Network is unreachable".to_string(),
+                "192.0.2.1:22: no answer".to_string(),
+            ],
+        };
+        assert!(error.is_retryable());
+        assert_eq!(
+            error.to_string(),
+            "Timed out connecting to example.com:22 after 15s ([2001:db8::1]:22: Network is unreachable, 192.0.2.1:22: no answer)"
+        );
+
+        let value = serde_json::to_value(&error).unwrap();
+        assert_eq!(value["code"], "timeout");
+        assert_eq!(value["details"]["errors"][1], "192.0.2.1:22: no answer");
+    }
+
+    #[test]
+    fn test_auth_failure_carries_report() {
+        let report = AuthReport {
+            method: None,
+            failures: vec![AuthFailure {
+                method: AuthMethod::Password,
+                reason: "Invalid credentials".to_string(),
+            }],
+        };
+        let value = serde_json::to_value(SSHError::Auth(report)).unwrap();
+
+        assert_eq!(value["code"], "auth_failed");
+        assert_eq!(value["retryable"], false);
+        assert_eq!(value["message"], "Authentication failed: Password authentication: Invalid credentials");
+        assert_eq!(value["details"]["authReport"]["failures"][0]["reason"], "Invalid credentials");
+    }
+
+    #[test]
+    fn test_jump_host_error_nests_its_cause() {
+        let error = SSHError::JumpHost {
+            name: "bastion".to_string(),
+            host: "10.0.0.1".to_string(),
+            port: 22,
+            cause: Box::new(SSHError::Timeout { operation: "connecting".to_string() }),
+        };
+        assert!(error.is_retryable());
+        assert_eq!(error.to_string(), "Jump host bastion (10.0.0.1:22): Timed out connecting");
+
+        let value = serde_json::to_value(&error).unwrap();
+        assert_eq!(value["code"], "jump_host");
+        assert_eq!(value["details"]["cause"]["code"], "timeout");
+    }
+
+    #[test]
+    fn test_not_found_and_validation() {
+        let error = SSHError::not_found(ResourceKind::TerminalSession, "abc");
+        assert_eq!(error.to_string(), "Terminal session abc not found");
+        assert_eq!(serde_json::to_value(&error).unwrap()["details"], json!({ "kind": "terminalSession", "id": "abc" }));
+
+        let error = SSHError::from(vec![ValidationError {
+            field: "port".to_string(),
+            message: "Port must be greater than 0".to_string(),
+        }]);
+        assert!(!error.is_retryable());
+        assert_eq!(error.to_string(), "Configuration validation failed: port: Port must be greater than 0");
+        assert_eq!(serde_json::to_value(&error).unwrap()["details"]["errors"][0]["field"], "port");
+    }
+}
@@ -0,0 +1,622 @@
+use crate::ssh::error::SSHError;
+use crate::ssh::types::*;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex as StdMutex};
+use tokio::sync::{mpsc, Mutex};
+
+/// Terminal output held back for a slow frontend before the oldest is dropped
+pub const MAX_PENDING_OUTPUT: usize = 8 * 1024 * 1024;
+
+/// Where dispatched events end up; the app emits them as Tauri events
+///
+/// `emit` may block. It runs on its own thread, so a slow sink only delays
+/// delivery and never the SSH handlers producing events.
+pub trait EventSink: Send + 'static {
+    fn emit(&self, name: &str, payload: &EventPayload) -> Result<(), String>;
+}
+
+/// The frontend-facing form of an `SSHEvent`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum EventPayload {
+    Connected {
+        connection_id: String,
+    },
+    Disconnected {
+        connection_id: String,
+    },
+    /// The connection dropped; attempt `attempt` to re-establish it starts after `delay_ms`
+    Reconnecting {
+        connection_id: String,
+        attempt: u32,
+        delay_ms: u64,
+    },
+    Error {
+        connection_id: String,
+        error: SSHError,
+    },
+    HostKeyUnknown {
+        connection_id: String,
+        hostname: String,
+        port: u16,
+        key_type: String,
+        fingerprint: String,
+    },
+    HostKeyChanged {
+        connection_id: String,
+        hostname: String,
+        port: u16,
+        known_fingerprint: String,
+        presented_fingerprint: String,
+    },
+    KeyPassphraseRequired {
+        connection_id: String,
+        key_path: String,
+    },
+    KeyboardInteractive {
+        connection_id: String,
+        challenge: KeyboardInteractiveChallenge,
+    },
+    TerminalCreated {
+        connection_id: String,
+        terminal_id: String,
+    },
+    Output {
+        connection_id: String,
+        terminal_id: String,
+        stream: OutputStream,
+        /// Where `data` starts among all the bytes the terminal has printed
+        offset: u64,
+        /// Raw bytes, which need not be valid UTF-8; base64 when serialized
+        #[serde(serialize_with = "serialize_base64")]
+        data: Vec<u8>,
+    },
+    /// Output discarded because the frontend fell too far behind
+    OutputDropped {
+        connection_id: String,
+        terminal_id: String,
+        bytes: usize,
+    },
+    TerminalResized {
+        connection_id: String,
+        terminal_id: String,
+        cols: u16,
+        rows: u16,
+    },
+    TerminalClosed {
+        connection_id: String,
+        terminal_id: String,
+    },
+    BatchHostStarted {
+        batch_id: String,
+        connection_id: String,
+    },
+    /// `finished` of the batch's `total` hosts are done, this one included
+    BatchHostFinished {
+        batch_id: String,
+        finished: usize,
+        total: usize,
+        result: BatchHostResult,
+    },
+}
+
+/// Name of the event carrying a connection's status, prompts and new terminals
+pub fn connection_event_name(connection_id: &str) -> String {
+    format!("ssh:connection:{}", connection_id)
+}
+
+/// Name of the event carrying a terminal's output, size changes and closure
+pub fn terminal_event_name(terminal_id: &str) -> String {
+    format!("ssh:terminal:{}", terminal_id)
+}
+
+/// Name of the event carrying a batch's per-host progress
+pub fn batch_event_name(batch_id: &str) -> String {
+    format!("ssh:batch:{}", batch_id)
+}
+
+impl EventPayload {
+    /// Terminal events go to the terminal's event, batch progress to the batch's, and
+    /// everything else to the connection's
+    pub fn event_name(&self) -> String {
+        match self {
+            EventPayload::BatchHostStarted { batch_id, .. }
+            | EventPayload::BatchHostFinished { batch_id, .. } => batch_event_name(batch_id),
+            EventPayload::Output { terminal_id, .. }
+            | EventPayload::OutputDropped { terminal_id, .. }
+            | EventPayload::TerminalResized { terminal_id, .. }
+            | EventPayload::TerminalClosed { terminal_id, .. } => terminal_event_name(terminal_id),
+            EventPayload::Connected { connection_id }
+            | EventPayload::Disconnected { connection_id }
+            | EventPayload::Reconnecting { connection_id, .. }
+            | EventPayload::Error { connection_id, .. }
+            | EventPayload::HostKeyUnknown { connection_id, .. }
+            | EventPayload::HostKeyChanged { connection_id, .. }
+            | EventPayload::KeyPassphraseRequired { connection_id, .. }
+            | EventPayload::KeyboardInteractive { connection_id, .. }
+            | EventPayload::TerminalCreated { connection_id, .. } => connection_event_name(connection_id),
+        }
+    }
+
+    fn output_len(&self) -> usize {
+        match self {
+            EventPayload::Output { data, .. } => data.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl From<SSHEvent> for EventPayload {
+    fn from(event: SSHEvent) -> Self {
+        match event {
+            SSHEvent::Connected(connection_id) => EventPayload::Connected { connection_id },
+            SSHEvent::Disconnected(connection_id) => EventPayload::Disconnected { connection_id },
+            SSHEvent::Reconnecting(connection_id, attempt, delay_ms) => EventPayload::Reconnecting {
+                connection_id,
+                attempt,
+                delay_ms,
+            },
+            SSHEvent::Data(connection_id, terminal_id, stream, offset, data) => EventPayload::Output {
+                connection_id,
+                terminal_id,
+                stream,
+                offset,
+                data,
+            },
+            SSHEvent::Error(connection_id, error) => EventPayload::Error { connection_id, error },
+            SSHEvent::TerminalCreated(connection_id, terminal_id) => EventPayload::TerminalCreated {
+                connection_id,
+                terminal_id,
+            },
+            SSHEvent::TerminalClosed(connection_id, terminal_id) => EventPayload::TerminalClosed {
+                connection_id,
+                terminal_id,
+            },
+            SSHEvent::TerminalResized(connection_id, terminal_id, cols, rows) => EventPayload::TerminalResized {
+                connection_id,
+                terminal_id,
+                cols,
+                rows,
+            },
+            SSHEvent::HostKeyUnknown(connection_id, hostname, port, key_type, fingerprint) => {
+                EventPayload::HostKeyUnknown {
+                    connection_id,
+                    hostname,
+                    port,
+                    key_type,
+                    fingerprint,
+                }
+            }
+            SSHEvent::HostKeyChanged(connection_id, hostname, port, known_fingerprint, presented_fingerprint) => {
+                EventPayload::HostKeyChanged {
+                    connection_id,
+                    hostname,
+                    port,
+                    known_fingerprint,
+                    presented_fingerprint,
+                }
+            }
+            SSHEvent::KeyPassphraseRequired(connection_id, key_path) => EventPayload::KeyPassphraseRequired {
+                connection_id,
+                key_path,
+            },
+            SSHEvent::KeyboardInteractive(connection_id, challenge) => EventPayload::KeyboardInteractive {
+                connection_id,
+                challenge,
+            },
+            SSHEvent::BatchHostStarted(batch_id, connection_id) => EventPayload::BatchHostStarted {
+                batch_id,
+                connection_id,
+            },
+            SSHEvent::BatchHostFinished(batch_id, finished, total, result) => EventPayload::BatchHostFinished {
+                batch_id,
+                finished,
+                total,
+                result: *result,
+            },
+        }
+    }
+}
+
+/// Events received but not yet emitted
+#[derive(Default)]
+struct Backlog {
+    events: VecDeque<EventPayload>,
+    output_bytes: usize,
+    /// Output in the event the sink is emitting; it counts against the limit until it's done
+    in_flight: usize,
+    closed: bool,
+}
+
+impl Backlog {
+    fn push(&mut self, payload: EventPayload, max_output: usize) {
+        self.output_bytes += payload.output_len();
+
+        // Consecutive chunks for the same stream become one event
+        match (self.events.back_mut(), payload) {
+            (
+                Some(EventPayload::Output { terminal_id, stream, offset, data, .. }),
+                EventPayload::Output {
+                    terminal_id: next_terminal,
+                    stream: next_stream,
+                    offset: next_offset,
+                    data: next_data,
+                    ..
+                },
+            ) if *terminal_id == next_terminal
+                && *stream == next_stream
+                && *offset + data.len() as u64 == next_offset => data.extend(next_data),
+            (_, payload) => self.events.push_back(payload),
+        }
+
+        self.trim(max_output);
+    }
+
+    /// Drops the oldest output until the backlog fits, leaving a marker in its place
+    fn trim(&mut self, max_output: usize) {
+        while self.output_bytes + self.in_flight > max_output {
+            let Some(index) = self.events.iter().position(|event| matches!(event, EventPayload::Output { .. })) else {
+                break;
+            };
+            let Some(EventPayload::Output { connection_id, terminal_id, data, .. }) = self.events.remove(index) else {
+                break;
+            };
+            self.output_bytes -= data.len();
+
+            // Runs of dropped output for one terminal share a marker
+            if let Some(EventPayload::OutputDropped { terminal_id: dropped_terminal, bytes, .. }) =
+                index.checked_sub(1).and_then(|previous| self.events.get_mut(previous))
+            {
+                if *dropped_terminal == terminal_id {
+                    *bytes += data.len();
+                    continue;
+                }
+            }
+            self.events.insert(
+                index,
+                EventPayload::OutputDropped {
+                    connection_id,
+                    terminal_id,
+                    bytes: data.len(),
+                },
+            );
+        }
+    }
+
+    /// Takes the next event to emit; the emitter only asks once the previous one is out
+    fn pop(&mut self) -> Option<EventPayload> {
+        self.in_flight = 0;
+        let payload = self.events.pop_front()?;
+        self.output_bytes -= payload.output_len();
+        self.in_flight = payload.output_len();
+        Some(payload)
+    }
+}
+
+/// Hands events from the receiving task to the emitting thread
+#[derive(Default)]
+struct Queue {
+    backlog: StdMutex<Backlog>,
+    ready: Condvar,
+}
+
+/// Forwards every event from `receiver` to `sink` until all senders are gone
+///
+/// Events are drained as soon as they arrive so senders never wait on the frontend;
+/// if it falls behind, the oldest terminal output beyond `MAX_PENDING_OUTPUT` is
+/// replaced by an `OutputDropped` marker. Other events are always delivered, in order.
+///
+/// The receiver has a single consumer: this holds its lock for as long as it runs.
+pub async fn dispatch_events<S: EventSink>(receiver: Arc<Mutex<mpsc::Receiver<SSHEvent>>>, sink: S) {
+    dispatch_events_with_limit(receiver, sink, MAX_PENDING_OUTPUT).await
+}
+
+async fn dispatch_events_with_limit<S: EventSink>(
+    receiver: Arc<Mutex<mpsc::Receiver<SSHEvent>>>,
+    sink: S,
+    max_output: usize,
+) {
+    let queue = Arc::new(Queue::default());
+
+    let emitter = {
+        let queue = queue.clone();
+        tokio::task::spawn_blocking(move || loop {
+            let payload = {
+                let mut backlog = queue.backlog.lock().unwrap();
+                loop {
+                    if let Some(payload) = backlog.pop() {
+                        break payload;
+                    }
+                    if backlog.closed {
+                        return;
+                    }
+                    backlog = queue.ready.wait(backlog).unwrap();
+                }
+            };
+            // A failed emit only affects that event; the webview may simply be gone
+            let _ = sink.emit(&payload.event_name(), &payload);
+        })
+    };
+
+    let mut receiver = receiver.lock().await;
+    while let Some(event) = receiver.recv().await {
+        queue.backlog.lock().unwrap().push(event.into(), max_output);
+        queue.ready.notify_one();
+    }
+
+    queue.backlog.lock().unwrap().closed = true;
+    queue.ready.notify_one();
+    let _ = emitter.await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{timeout, Duration};
+
+    type Emitted = Arc<StdMutex<Vec<(String, EventPayload)>>>;
+
+    /// Records emitted events; while `stalled` it blocks like a frontend that stopped reading
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        emitted: Emitted,
+        stalled: Arc<(StdMutex<bool>, Condvar)>,
+    }
+
+    impl RecordingSink {
+        fn set_stalled(&self, stalled: bool) {
+            *self.stalled.0.lock().unwrap() = stalled;
+            self.stalled.1.notify_all();
+        }
+    }
+
+    impl EventSink for RecordingSink {
+        fn emit(&self, name: &str, payload: &EventPayload) -> Result<(), String> {
+            let (stalled, resumed) = &*self.stalled;
+            drop(resumed.wait_while(stalled.lock().unwrap(), |stalled| *stalled).unwrap());
+            self.emitted.lock().unwrap().push((name.to_string(), payload.clone()));
+            Ok(())
+        }
+    }
+
+    fn output(terminal_id: &str, stream: OutputStream, offset: u64, data: &[u8]) -> SSHEvent {
+        SSHEvent::Data("conn-1".to_string(), terminal_id.to_string(), stream, offset, data.to_vec())
+    }
+
+    /// Sends `events` through a dispatcher and returns everything the sink saw
+    async fn dispatch(events: Vec<SSHEvent>) -> Vec<(String, EventPayload)> {
+        let (sender, receiver) = mpsc::channel(10);
+        let sink = RecordingSink::default();
+        let dispatcher = tokio::spawn(dispatch_events(Arc::new(Mutex::new(receiver)), sink.clone()));
+
+        for event in events {
+            sender.send(event).await.unwrap();
+        }
+        drop(sender);
+        timeout(Duration::from_secs(5), dispatcher).await.unwrap().unwrap();
+
+        let emitted = sink.emitted.lock().unwrap().clone();
+        emitted
+    }
+
+    #[tokio::test]
+    async fn test_events_are_named_by_connection_and_terminal() {
+        let emitted = dispatch(vec![
+            SSHEvent::Connected("conn-1".to_string()),
+            SSHEvent::TerminalCreated("conn-1".to_string(), "term-1".to_string()),
+            SSHEvent::TerminalResized("conn-1".to_string(), "term-1".to_string(), 100, 30),
+            SSHEvent::TerminalClosed("conn-1".to_string(), "term-1".to_string()),
+            SSHEvent::Error("conn-2".to_string(), SSHError::NotConnected),
+            SSHEvent::BatchHostStarted("batch-1".to_string(), "conn-1".to_string()),
+        ])
+        .await;
+
+        let names: Vec<_> = emitted.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "ssh:connection:conn-1",
+                "ssh:connection:conn-1",
+                "ssh:terminal:term-1",
+                "ssh:terminal:term-1",
+                "ssh:connection:conn-2",
+                "ssh:batch:batch-1",
+            ]
+        );
+        assert_eq!(
+            emitted[2].1,
+            EventPayload::TerminalResized {
+                connection_id: "conn-1".to_string(),
+                terminal_id: "term-1".to_string(),
+                cols: 100,
+                rows: 30,
+            }
+        );
+    }
+
+    #[test]
+    fn test_payload_serialization() {
+        let payload = EventPayload::from(SSHEvent::HostKeyUnknown(
+            "conn-1".to_string(),
+            "example.com".to_string(),
+            22,
+            "ssh-ed25519".to_string(),
+            "SHA256:abc".to_string(),
+        ));
+
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "type": "hostKeyUnknown",
+                "connectionId": "conn-1",
+                "hostname": "example.com",
+                "port": 22,
+                "keyType": "ssh-ed25519",
+                "fingerprint": "SHA256:abc",
+            })
+        );
+
+        let payload = EventPayload::from(SSHEvent::HostKeyChanged(
+            "conn-1".to_string(),
+            "example.com".to_string(),
+            2222,
+            "SHA256:old".to_string(),
+            "SHA256:new".to_string(),
+        ));
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "type": "hostKeyChanged",
+                "connectionId": "conn-1",
+                "hostname": "example.com",
+                "port": 2222,
+                "knownFingerprint": "SHA256:old",
+                "presentedFingerprint": "SHA256:new",
+            })
+        );
+
+        let payload = EventPayload::from(output("term-1", OutputStream::Stderr, 42, b"hi"));
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "type": "output",
+                "connectionId": "conn-1",
+                "terminalId": "term-1",
+                "stream": "Stderr",
+                "offset": 42,
+                "data": "aGk=",
+            })
+        );
+
+        let payload = EventPayload::from(SSHEvent::Reconnecting("conn-1".to_string(), 3, 4000));
+        assert_eq!(payload.event_name(), "ssh:connection:conn-1");
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "type": "reconnecting",
+                "connectionId": "conn-1",
+                "attempt": 3,
+                "delayMs": 4000,
+            })
+        );
+
+        let payload = EventPayload::from(SSHEvent::Error("conn-1".to_string(), SSHError::NotConnected));
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            serde_json::json!({
+                "type": "error",
+                "connectionId": "conn-1",
+                "error": {
+                    "code": "not_connected",
+                    "message": "SSH session not available",
+                    "retryable": false,
+                    "details": null,
+                },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_order_is_preserved_across_terminals() {
+        let emitted = dispatch(vec![
+            output("term-1", OutputStream::Stdout, 0, b"a"),
+            output("term-2", OutputStream::Stdout, 0, b"b"),
+            output("term-1", OutputStream::Stderr, 1, b"c"),
+            SSHEvent::TerminalClosed("conn-1".to_string(), "term-1".to_string()),
+        ])
+        .await;
+
+        let data: Vec<_> = emitted
+            .iter()
+            .map(|(name, payload)| match payload {
+                EventPayload::Output { data, .. } => format!("{}={}", name, String::from_utf8_lossy(data)),
+                _ => format!("{}=closed", name),
+            })
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                "ssh:terminal:term-1=a",
+                "ssh:terminal:term-2=b",
+                "ssh:terminal:term-1=c",
+                "ssh:terminal:term-1=closed",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stalled_sink_does_not_block_senders() {
+        let (sender, receiver) = mpsc::channel(10);
+        let sink = RecordingSink::default();
+        sink.set_stalled(true);
+        let dispatcher = tokio::spawn(dispatch_events_with_limit(
+            Arc::new(Mutex::new(receiver)),
+            sink.clone(),
+            64 * 1024,
+        ));
+
+        // Far more events than the channel holds, while the sink is stuck
+        timeout(Duration::from_secs(5), async {
+            for offset in (0..1000).map(|chunk| chunk * 1024) {
+                sender.send(output("term-1", OutputStream::Stdout, offset, &[b'x'; 1024])).await.unwrap();
+            }
+            sender.send(SSHEvent::TerminalClosed("conn-1".to_string(), "term-1".to_string())).await.unwrap();
+        })
+        .await
+        .expect("senders were blocked by the sink");
+
+        sink.set_stalled(false);
+        drop(sender);
+        timeout(Duration::from_secs(5), dispatcher).await.unwrap().unwrap();
+
+        let emitted = sink.emitted.lock().unwrap();
+        let mut delivered = 0;
+        let mut dropped = 0;
+        for (_, payload) in emitted.iter() {
+            match payload {
+                EventPayload::Output { data, .. } => delivered += data.len(),
+                EventPayload::OutputDropped { bytes, .. } => dropped += bytes,
+                _ => {}
+            }
+        }
+
+        // Output beyond the limit was dropped and accounted for; control events survive
+        assert_eq!(delivered + dropped, 1000 * 1024);
+        assert!(dropped > 0);
+        assert!(delivered <= 64 * 1024 + 1024);
+        assert!(matches!(emitted.last(), Some((_, EventPayload::TerminalClosed { .. }))));
+    }
+
+    #[test]
+    fn test_backlog_coalesces_and_trims() {
+        let mut backlog = Backlog::default();
+        for (offset, chunk) in [(0, b"ab"), (2, b"cd")] {
+            backlog.push(output("term-1", OutputStream::Stdout, offset, chunk).into(), 100);
+        }
+        backlog.push(output("term-1", OutputStream::Stderr, 4, b"ef").into(), 100);
+        assert_eq!(backlog.events.len(), 2);
+        assert_eq!(backlog.output_bytes, 6);
+
+        // Going over the limit drops the oldest chunk first
+        backlog.push(output("term-2", OutputStream::Stdout, 0, b"ghijk").into(), 8);
+        assert_eq!(backlog.output_bytes, 7);
+        assert_eq!(
+            backlog.pop(),
+            Some(EventPayload::OutputDropped {
+                connection_id: "conn-1".to_string(),
+                terminal_id: "term-1".to_string(),
+                bytes: 4,
+            })
+        );
+        assert_eq!(backlog.pop().unwrap().output_len(), 2);
+        assert_eq!(backlog.pop().unwrap().output_len(), 5);
+        assert_eq!(backlog.output_bytes, 0);
+        assert_eq!(backlog.pop(), None);
+
+        // Chunks that don't follow on from each other keep their own offsets
+        backlog.push(output("term-1", OutputStream::Stdout, 0, b"ab").into(), 100);
+        backlog.push(output("term-1", OutputStream::Stdout, 10, b"cd").into(), 100);
+        assert_eq!(backlog.events.len(), 2);
+    }
+}
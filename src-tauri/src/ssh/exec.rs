@@ -0,0 +1,116 @@
+//! One-off commands run on their own session channels, outside any terminal
+
+use crate::ssh::error::{SSHError, SSHResult};
+use crate::ssh::terminal::SessionEnded;
+use crate::ssh::types::{ExecRequest, ExecResult, OutputStream};
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg, Sig};
+use tokio::time::{timeout, Instant};
+
+/// What a command has printed so far, up to the request's limit
+struct Output {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    remaining: usize,
+    truncated: bool,
+    exit_status: Option<u32>,
+    exit_signal: Option<String>,
+}
+
+impl Output {
+    fn new(limit: usize) -> Self {
+        Self {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            remaining: limit,
+            truncated: false,
+            exit_status: None,
+            exit_signal: None,
+        }
+    }
+
+    /// Keeps as much of `data` as the limit allows
+    fn keep(&mut self, stream: OutputStream, data: &[u8]) {
+        let kept = data.len().min(self.remaining);
+        let buffer = match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        };
+        buffer.extend_from_slice(&data[..kept]);
+        self.remaining -= kept;
+        self.truncated |= kept < data.len();
+    }
+}
+
+/// Runs the request's command on `channel`, a fresh session channel, and collects its
+/// output until it exits, its output reaches the limit or it runs out of time. A command
+/// that is cut short is stopped by closing its channel, and what it printed until then
+/// is returned.
+pub async fn run(mut channel: Channel<Msg>, session_ended: SessionEnded, request: &ExecRequest) -> SSHResult<ExecResult> {
+    let started = Instant::now();
+    channel
+        .exec(true, request.command.as_bytes())
+        .await
+        .map_err(|e| SSHError::channel("Failed to run the command", e))?;
+
+    let mut output = Output::new(request.max_output_bytes());
+    let timed_out = match timeout(request.timeout(), collect(&mut channel, &mut output)).await {
+        Ok(collected) => {
+            collected?;
+            false
+        }
+        Err(_) => true,
+    };
+
+    if output.truncated || timed_out {
+        let _ = channel.close().await;
+    } else if output.exit_status.is_none() && output.exit_signal.is_none() {
+        // The channel went away with the session rather than with the command
+        if let Some(error) = session_ended.borrow().clone() {
+            return Err(error);
+        }
+    }
+
+    Ok(ExecResult {
+        command: request.command.clone(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        exit_status: output.exit_status,
+        exit_signal: output.exit_signal,
+        truncated: output.truncated,
+        timed_out,
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// Reads the channel into `output` until it closes or the output reaches its limit
+async fn collect(channel: &mut Channel<Msg>, output: &mut Output) -> SSHResult<()> {
+    while let Some(message) = channel.wait().await {
+        match message {
+            ChannelMsg::Data { data } => output.keep(OutputStream::Stdout, &data),
+            // Type 1 (stderr) is the only extended data type SSH defines
+            ChannelMsg::ExtendedData { data, ext: 1 } => output.keep(OutputStream::Stderr, &data),
+            ChannelMsg::ExitStatus { exit_status } => output.exit_status = Some(exit_status),
+            ChannelMsg::ExitSignal { signal_name, .. } => output.exit_signal = Some(signal_label(signal_name)),
+            ChannelMsg::Failure => {
+                return Err(SSHError::Channel {
+                    reason: "The server refused to run the command".to_string(),
+                });
+            }
+            _ => {}
+        }
+
+        if output.truncated {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The signal's name without the `SIG` prefix, as the protocol sends it
+fn signal_label(signal: Sig) -> String {
+    match signal {
+        Sig::Custom(name) => name,
+        signal => format!("{:?}", signal),
+    }
+}
@@ -0,0 +1,549 @@
+use crate::ssh::error::{ResourceKind, SSHError, SSHResult};
+use crate::ssh::socks;
+use crate::ssh::types::*;
+use russh::client::Msg;
+use russh::Channel;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{timeout, Duration};
+
+/// Size of the buffer each direction of a forwarded connection is copied through
+const COPY_BUFFER_SIZE: usize = 32 * 1024;
+
+/// How long a SOCKS client has to say where it wants to connect
+const SOCKS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a listener waits before accepting again after an accept fails
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Opens the channels forwarded connections are carried over, and asks the server to
+/// listen for remote forwards
+#[async_trait::async_trait]
+pub trait TunnelOpener: Send + Sync + 'static {
+    /// Opens a `direct-tcpip` channel to `host:port` for a client connected from `originator`
+    async fn open_direct_tcpip(&self, host: &str, port: u16, originator: SocketAddr) -> SSHResult<Channel<Msg>>;
+
+    /// Asks the server to listen on `address:port` (`tcpip-forward`) and returns the port it
+    /// listens on, which it picks when `port` is 0
+    async fn request_remote_listener(&self, address: &str, port: u16) -> SSHResult<u16>;
+
+    /// Asks the server to stop listening on `address:port` (`cancel-tcpip-forward`)
+    async fn cancel_remote_listener(&self, address: &str, port: u16) -> SSHResult<()>;
+}
+
+/// The port forwards running on one connection
+#[derive(Default)]
+pub struct PortForwardManager {
+    forwards: Arc<RwLock<HashMap<String, ForwardData>>>,
+}
+
+struct ForwardData {
+    forward: PortForward,
+    counters: Arc<ForwardCounters>,
+    tasks: ForwardTasks,
+}
+
+/// What keeps a forward running; dropping it stops the forward's connections
+enum ForwardTasks {
+    /// The accept loop of a forward listening on this machine, which owns the connections
+    /// it is forwarding
+    Local(JoinHandle<()>),
+    /// A remote forward the server listens for on `port`, and the connections it has
+    /// opened to it
+    Remote {
+        port: u16,
+        tunnels: Arc<dyn TunnelOpener>,
+        connections: JoinSet<()>,
+    },
+}
+
+#[derive(Default)]
+struct ForwardCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    connections: AtomicU64,
+    active_connections: AtomicU64,
+}
+
+impl ForwardCounters {
+    fn snapshot(&self) -> ForwardStats {
+        ForwardStats {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            active_connections: self.active_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl PortForwardManager {
+    /// Listens on the rule's local address and forwards every connection accepted there
+    /// through a channel from `tunnels`
+    pub async fn start_local(
+        &self,
+        connection_id: &str,
+        rule: LocalForward,
+        tunnels: Arc<dyn TunnelOpener>,
+    ) -> SSHResult<PortForward> {
+        rule.validate()?;
+
+        let target = ListenTarget::Fixed {
+            host: rule.target_host.clone(),
+            port: rule.target_port,
+        };
+        let (address, port) = (rule.bind_address.clone(), rule.bind_port);
+        self.listen(connection_id, ForwardRule::Local(rule), &address, port, target, tunnels).await
+    }
+
+    /// Runs a SOCKS proxy on the rule's local address, carrying each connection it accepts
+    /// through a channel from `tunnels` to wherever the client asks
+    pub async fn start_dynamic(
+        &self,
+        connection_id: &str,
+        rule: DynamicForward,
+        tunnels: Arc<dyn TunnelOpener>,
+    ) -> SSHResult<PortForward> {
+        rule.validate()?;
+
+        let (address, port) = (rule.bind_address.clone(), rule.bind_port);
+        self.listen(connection_id, ForwardRule::Dynamic(rule), &address, port, ListenTarget::Socks, tunnels).await
+    }
+
+    async fn listen(
+        &self,
+        connection_id: &str,
+        rule: ForwardRule,
+        address: &str,
+        port: u16,
+        target: ListenTarget,
+        tunnels: Arc<dyn TunnelOpener>,
+    ) -> SSHResult<PortForward> {
+        let listener = TcpListener::bind((address, port))
+            .await
+            .map_err(|e| bind_error(address, port, e))?;
+        let local_address = listener
+            .local_addr()
+            .map_err(|e| bind_error(address, port, e))?;
+
+        let forward = new_forward(connection_id, rule, local_address.to_string());
+        let counters = Arc::new(ForwardCounters::default());
+        let accept_task = tokio::spawn(accept_local(listener, target, tunnels, counters.clone()));
+
+        self.insert(forward.clone(), counters, ForwardTasks::Local(accept_task)).await;
+        Ok(forward)
+    }
+
+    /// Asks the server to listen on the rule's address; connections made to it there are
+    /// handed over through `accept_remote` and connected to the rule's local target
+    pub async fn start_remote(
+        &self,
+        connection_id: &str,
+        rule: RemoteForward,
+        tunnels: Arc<dyn TunnelOpener>,
+    ) -> SSHResult<PortForward> {
+        rule.validate()?;
+
+        let port = tunnels.request_remote_listener(&rule.bind_address, rule.bind_port).await?;
+        let listen_address = format!("{}:{}", rule.bind_address, port);
+        let forward = new_forward(connection_id, ForwardRule::Remote(rule), listen_address);
+        let tasks = ForwardTasks::Remote {
+            port,
+            tunnels,
+            connections: JoinSet::new(),
+        };
+
+        self.insert(forward.clone(), Arc::new(ForwardCounters::default()), tasks).await;
+        Ok(forward)
+    }
+
+    async fn insert(&self, forward: PortForward, counters: Arc<ForwardCounters>, tasks: ForwardTasks) {
+        self.forwards.write().await.insert(
+            forward.id.clone(),
+            ForwardData {
+                forward,
+                counters,
+                tasks,
+            },
+        );
+    }
+
+    /// Takes a `forwarded-tcpip` channel the server opened for a connection to
+    /// `address:port` and carries it to the local target of the remote forward listening
+    /// there. The channel is closed if no forward is.
+    pub async fn accept_remote(&self, channel: Channel<Msg>, address: &str, port: u32) {
+        let mut forwards = self.forwards.write().await;
+
+        // Servers report the address as it was requested, so a port match is enough when
+        // the address was spelled differently
+        let target = forwards
+            .values_mut()
+            .filter_map(|data| match (&data.forward.rule, &mut data.tasks) {
+                (ForwardRule::Remote(rule), ForwardTasks::Remote { port: listening, connections, .. })
+                    if u32::from(*listening) == port =>
+                {
+                    let exact = rule.bind_address == address;
+                    Some((exact, rule, &data.counters, connections))
+                }
+                _ => None,
+            })
+            .max_by_key(|(exact, ..)| *exact);
+
+        let Some((_, rule, counters, connections)) = target else {
+            drop(forwards);
+            let _ = channel.close().await;
+            return;
+        };
+
+        // Reap the connections that have finished so the set doesn't grow with every one
+        while connections.try_join_next().is_some() {}
+        counters.connections.fetch_add(1, Ordering::Relaxed);
+        connections.spawn(forward_remote_connection(
+            channel,
+            rule.target_host.clone(),
+            rule.target_port,
+            counters.clone(),
+        ));
+    }
+
+    /// Asks the server to listen again for every remote forward, on the port it had, after
+    /// the connection was re-established. Forwards the server refuses are stopped and
+    /// their errors returned.
+    pub async fn restore_remote(&self) -> Vec<SSHError> {
+        let remote: Vec<(String, String, u16, Arc<dyn TunnelOpener>)> = {
+            let forwards = self.forwards.read().await;
+            forwards
+                .values()
+                .filter_map(|data| match (&data.forward.rule, &data.tasks) {
+                    (ForwardRule::Remote(rule), ForwardTasks::Remote { port, tunnels, .. }) => {
+                        Some((data.forward.id.clone(), rule.bind_address.clone(), *port, tunnels.clone()))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let mut errors = Vec::new();
+        for (forward_id, address, port, tunnels) in remote {
+            if let Err(error) = tunnels.request_remote_listener(&address, port).await {
+                self.forwards.write().await.remove(&forward_id);
+                errors.push(error);
+            }
+        }
+        errors
+    }
+
+    /// Gets a forward along with its current traffic counters
+    pub async fn get(&self, forward_id: &str) -> Option<PortForward> {
+        let forwards = self.forwards.read().await;
+        forwards.get(forward_id).map(ForwardData::current)
+    }
+
+    /// Lists the running forwards, oldest first
+    pub async fn list(&self) -> Vec<PortForward> {
+        let forwards = self.forwards.read().await;
+        let mut list: Vec<PortForward> = forwards.values().map(ForwardData::current).collect();
+        list.sort_by_key(|forward| forward.created_at);
+        list
+    }
+
+    /// Whether a forward for exactly this rule is already running
+    pub async fn is_running(&self, rule: &ForwardRule) -> bool {
+        let forwards = self.forwards.read().await;
+        forwards.values().any(|data| &data.forward.rule == rule)
+    }
+
+    /// Stops listening and closes the connections the forward is carrying. The server is
+    /// asked to stop listening for a remote forward.
+    pub async fn stop(&self, forward_id: &str) -> SSHResult<()> {
+        let data = self.forwards.write().await.remove(forward_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::PortForward, forward_id))?;
+        data.cancel_remote().await;
+        Ok(())
+    }
+
+    /// Stops every forward
+    pub async fn stop_all(&self) {
+        let stopped: Vec<ForwardData> = self.forwards.write().await.drain().map(|(_, data)| data).collect();
+        for data in stopped {
+            data.cancel_remote().await;
+        }
+    }
+}
+
+impl ForwardData {
+    fn current(&self) -> PortForward {
+        PortForward {
+            stats: self.counters.snapshot(),
+            ..self.forward.clone()
+        }
+    }
+
+    /// Asks the server to stop listening for a remote forward. The forward is stopped on
+    /// this side either way: connections the server still hands over find no forward and
+    /// are closed.
+    async fn cancel_remote(&self) {
+        if let (ForwardRule::Remote(rule), ForwardTasks::Remote { port, tunnels, .. }) = (&self.forward.rule, &self.tasks) {
+            let _ = tunnels.cancel_remote_listener(&rule.bind_address, *port).await;
+        }
+    }
+}
+
+impl Drop for ForwardData {
+    fn drop(&mut self) {
+        if let ForwardTasks::Local(accept_task) = &self.tasks {
+            accept_task.abort();
+        }
+    }
+}
+
+fn new_forward(connection_id: &str, rule: ForwardRule, listen_address: String) -> PortForward {
+    PortForward {
+        id: uuid::Uuid::new_v4().to_string(),
+        connection_id: connection_id.to_string(),
+        rule,
+        listen_address,
+        stats: ForwardStats::default(),
+        created_at: chrono::Utc::now(),
+    }
+}
+
+fn bind_error(address: &str, port: u16, error: std::io::Error) -> SSHError {
+    if error.kind() == std::io::ErrorKind::AddrInUse {
+        SSHError::AddressInUse { address: address.to_string(), port }
+    } else {
+        SSHError::Bind { address: address.to_string(), port, reason: error.to_string() }
+    }
+}
+
+/// Where a forward listening on this machine sends the connections it accepts
+#[derive(Clone)]
+enum ListenTarget {
+    Fixed { host: String, port: u16 },
+    /// Wherever each client asks, through the SOCKS handshake
+    Socks,
+}
+
+/// Accepts connections for a local or dynamic forward until it is stopped. Each
+/// connection is tracked in a `JoinSet`, so stopping the forward also stops them.
+async fn accept_local(
+    listener: TcpListener,
+    target: ListenTarget,
+    tunnels: Arc<dyn TunnelOpener>,
+    counters: Arc<ForwardCounters>,
+) {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                // Failing to accept one connection (e.g. out of file descriptors) doesn't stop the
+                // forward, but the error is likely to persist for a while, so don't spin on it
+                let Ok((stream, originator)) = accepted else {
+                    tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                    continue;
+                };
+                counters.connections.fetch_add(1, Ordering::Relaxed);
+                match &target {
+                    ListenTarget::Fixed { host, port } => connections.spawn(forward_local_connection(
+                        stream,
+                        originator,
+                        host.clone(),
+                        *port,
+                        tunnels.clone(),
+                        counters.clone(),
+                    )),
+                    ListenTarget::Socks => connections.spawn(forward_socks_connection(
+                        stream,
+                        originator,
+                        tunnels.clone(),
+                        counters.clone(),
+                    )),
+                };
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+}
+
+/// Carries one accepted connection over a new channel until both sides are done.
+/// The client is simply disconnected if the channel can't be opened, as with OpenSSH.
+async fn forward_local_connection(
+    stream: TcpStream,
+    originator: SocketAddr,
+    target_host: String,
+    target_port: u16,
+    tunnels: Arc<dyn TunnelOpener>,
+    counters: Arc<ForwardCounters>,
+) {
+    let Ok(channel) = tunnels.open_direct_tcpip(&target_host, target_port, originator).await else {
+        return;
+    };
+    bridge(stream, channel, &counters).await;
+}
+
+/// Reads a SOCKS client's request and carries the connection over a channel to the host
+/// it asked for. Clients that don't finish the handshake are disconnected; ones whose
+/// channel can't be opened are told so first.
+async fn forward_socks_connection(
+    mut stream: TcpStream,
+    originator: SocketAddr,
+    tunnels: Arc<dyn TunnelOpener>,
+    counters: Arc<ForwardCounters>,
+) {
+    let Ok(Ok(request)) = timeout(SOCKS_HANDSHAKE_TIMEOUT, socks::accept(&mut stream)).await else {
+        return;
+    };
+
+    let channel = match tunnels.open_direct_tcpip(&request.host, request.port, originator).await {
+        Ok(channel) => channel,
+        Err(_) => {
+            let _ = socks::reply(&mut stream, request.version, false).await;
+            return;
+        }
+    };
+    if socks::reply(&mut stream, request.version, true).await.is_err() {
+        let _ = channel.close().await;
+        return;
+    }
+    bridge(stream, channel, &counters).await;
+}
+
+/// Connects a channel the server opened for a remote forward to the local target and
+/// carries it until both sides are done. The channel is closed if the target can't be reached.
+async fn forward_remote_connection(
+    channel: Channel<Msg>,
+    target_host: String,
+    target_port: u16,
+    counters: Arc<ForwardCounters>,
+) {
+    let Ok(stream) = TcpStream::connect((target_host.as_str(), target_port)).await else {
+        let _ = channel.close().await;
+        return;
+    };
+    bridge(stream, channel, &counters).await;
+}
+
+/// Copies between a local socket and a channel in both directions until both are done
+async fn bridge(stream: TcpStream, channel: Channel<Msg>, counters: &ForwardCounters) {
+    counters.active_connections.fetch_add(1, Ordering::Relaxed);
+    let (local_read, local_write) = stream.into_split();
+    let (remote_read, remote_write) = tokio::io::split(channel.into_stream());
+    tokio::join!(
+        copy_counted(local_read, remote_write, &counters.bytes_sent),
+        copy_counted(remote_read, local_write, &counters.bytes_received),
+    );
+    counters.active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Copies one direction of a forwarded connection, counting the bytes as they go, then
+/// passes the end of the stream on
+async fn copy_counted<R, W>(mut from: R, mut to: W, counter: &AtomicU64)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let read = match from.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        if to.write_all(&buffer[..read]).await.is_err() {
+            break;
+        }
+        counter.fetch_add(read as u64, Ordering::Relaxed);
+    }
+    let _ = to.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a connection that has no session
+    struct NoTunnels;
+
+    #[async_trait::async_trait]
+    impl TunnelOpener for NoTunnels {
+        async fn open_direct_tcpip(&self, _host: &str, _port: u16, _originator: SocketAddr) -> SSHResult<Channel<Msg>> {
+            Err(SSHError::NotConnected)
+        }
+
+        async fn request_remote_listener(&self, _address: &str, _port: u16) -> SSHResult<u16> {
+            Err(SSHError::NotConnected)
+        }
+
+        async fn cancel_remote_listener(&self, _address: &str, _port: u16) -> SSHResult<()> {
+            Err(SSHError::NotConnected)
+        }
+    }
+
+    fn rule(bind_port: u16) -> LocalForward {
+        LocalForward {
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
+            bind_port,
+            target_host: "db.internal".to_string(),
+            target_port: 5432,
+            auto_start: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_conflict() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let forwards = PortForwardManager::default();
+
+        let error = forwards.start_local("conn-1", rule(port), Arc::new(NoTunnels)).await.unwrap_err();
+        assert_eq!(error, SSHError::AddressInUse { address: DEFAULT_BIND_ADDRESS.to_string(), port });
+        assert!(forwards.list().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_start_list_and_stop() {
+        let forwards = PortForwardManager::default();
+        let forward = forwards.start_local("conn-1", rule(0), Arc::new(NoTunnels)).await.unwrap();
+        let local_address: SocketAddr = forward.listen_address.parse().unwrap();
+        assert_ne!(local_address.port(), 0);
+        assert!(forwards.is_running(&ForwardRule::Local(rule(0))).await);
+
+        // Without a tunnel the client is disconnected, but the forward keeps listening
+        let mut client = TcpStream::connect(local_address).await.unwrap();
+        let mut buffer = [0; 1];
+        assert_eq!(client.read(&mut buffer).await.unwrap(), 0);
+        let listed = forwards.list().await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].stats.connections, 1);
+        assert_eq!(listed[0].stats.active_connections, 0);
+
+        forwards.stop(&forward.id).await.unwrap();
+        assert!(forwards.get(&forward.id).await.is_none());
+        assert_eq!(
+            forwards.stop(&forward.id).await,
+            Err(SSHError::not_found(ResourceKind::PortForward, &forward.id))
+        );
+        // The listener closes once the aborted accept task is dropped
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while TcpListener::bind(local_address).await.is_err() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the listener was never closed");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_rule_is_rejected() {
+        let forwards = PortForwardManager::default();
+        let error = forwards
+            .start_local("conn-1", LocalForward { target_port: 0, ..rule(0) }, Arc::new(NoTunnels))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SSHError::Validation(_)));
+    }
+}
@@ -0,0 +1,529 @@
+use crate::ssh::credentials::{CredentialRef, CredentialStore};
+use crate::ssh::error::{ResourceKind, SSHError, SSHResult};
+use crate::ssh::types::SSHConnectionConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// Version of the on-disk format written by this build
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Name of the inventory file inside the app data directory
+pub const INVENTORY_FILE_NAME: &str = "hosts.json";
+
+/// A step that upgrades a document from schema `N` (its index) to `N + 1`
+type Migration = fn(Value) -> Result<Value, String>;
+
+/// Forward migrations, indexed by the schema version they upgrade from
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Schema 0 is a bare array of connections with no version; schema 1 wraps it
+fn migrate_v0_to_v1(document: Value) -> Result<Value, String> {
+    match document {
+        Value::Array(hosts) => Ok(serde_json::json!({ "version": 1, "hosts": hosts })),
+        _ => Err("expected a list of hosts".to_string()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct InventoryDocument {
+    version: u32,
+    hosts: Vec<SSHConnectionConfig>,
+}
+
+/// Saved host definitions, kept in a JSON file under the app data directory
+///
+/// The inventory is independent of live connections: editing or deleting a host
+/// never touches a session that is already open. Secrets go to the keyring and
+/// only their references are written to disk.
+pub struct HostInventory {
+    path: PathBuf,
+    credential_store: CredentialStore,
+    /// Serialises read-modify-write cycles on the file
+    lock: Mutex<()>,
+}
+
+impl HostInventory {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            credential_store: CredentialStore::system(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Keeps host secrets in the given keyring instead of the platform one
+    pub fn with_credential_store(mut self, credential_store: CredentialStore) -> Self {
+        self.credential_store = credential_store;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns every saved host, in the order they were created
+    pub async fn list(&self) -> SSHResult<Vec<SSHConnectionConfig>> {
+        let _guard = self.lock.lock().await;
+        self.load()
+    }
+
+    pub async fn get(&self, host_id: &str) -> SSHResult<SSHConnectionConfig> {
+        let _guard = self.lock.lock().await;
+        self.load()?
+            .into_iter()
+            .find(|host| host.id == host_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::Host, host_id))
+    }
+
+    /// Saves a new host, generating its id if it has none, and returns it as stored
+    pub async fn create(&self, mut config: SSHConnectionConfig) -> SSHResult<SSHConnectionConfig> {
+        let _guard = self.lock.lock().await;
+        let mut hosts = self.load()?;
+
+        if config.id.is_empty() {
+            config.id = uuid::Uuid::new_v4().to_string();
+        }
+        if hosts.iter().any(|host| host.id == config.id) {
+            return Err(SSHError::InvalidState(format!("Host {} already exists", config.id)));
+        }
+        validate(&config, &hosts)?;
+
+        let written = self.store_secrets(&mut config, None)?;
+        hosts.push(config.clone());
+        if let Err(e) = self.save(&hosts) {
+            self.restore_secrets(written);
+            return Err(e);
+        }
+        Ok(config)
+    }
+
+    /// Replaces a saved host's definition and returns it as stored
+    pub async fn update(&self, mut config: SSHConnectionConfig) -> SSHResult<SSHConnectionConfig> {
+        let _guard = self.lock.lock().await;
+        let mut hosts = self.load()?;
+
+        let index = hosts
+            .iter()
+            .position(|host| host.id == config.id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::Host, &config.id))?;
+        validate(&config, &hosts)?;
+
+        let previous = hosts[index].clone();
+        let written = self.store_secrets(&mut config, Some(&previous))?;
+        hosts[index] = config.clone();
+        if let Err(e) = self.save(&hosts) {
+            self.restore_secrets(written);
+            return Err(e);
+        }
+
+        // Drop secrets the new definition no longer refers to, now nothing on disk does
+        let mut unused = SSHConnectionConfig {
+            password_ref: previous.password_ref.clone().filter(|_| config.password_ref.is_none()),
+            key_passphrase_ref: previous.key_passphrase_ref.clone().filter(|_| config.key_passphrase_ref.is_none()),
+            ..previous
+        };
+        unused.forget_secrets(&self.credential_store)?;
+        Ok(config)
+    }
+
+    /// Removes a saved host and its secrets; hosts other hosts jump through can't be removed
+    pub async fn delete(&self, host_id: &str) -> SSHResult<()> {
+        let _guard = self.lock.lock().await;
+        let mut hosts = self.load()?;
+
+        let index = hosts
+            .iter()
+            .position(|host| host.id == host_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::Host, host_id))?;
+        if let Some(dependent) = hosts.iter().find(|host| host.jump_hosts.iter().any(|id| id == host_id)) {
+            return Err(SSHError::InvalidState(format!(
+                "Host {} is a jump host for {}",
+                hosts[index].name, dependent.name
+            )));
+        }
+
+        let mut removed = hosts.remove(index);
+        self.save(&hosts)?;
+        removed.forget_secrets(&self.credential_store)
+    }
+
+    /// Moves a host's secrets into the keyring. Returns each entry written along with the
+    /// secret it replaced, if `previous` pointed at it, so a failed save can be undone.
+    fn store_secrets(
+        &self,
+        config: &mut SSHConnectionConfig,
+        previous: Option<&SSHConnectionConfig>,
+    ) -> SSHResult<Vec<(CredentialRef, Option<String>)>> {
+        let mut replaced = Vec::new();
+        if let Some(previous) = previous {
+            for credential in [&previous.password_ref, &previous.key_passphrase_ref].into_iter().flatten() {
+                replaced.push((credential.clone(), self.credential_store.load(credential)?));
+            }
+        }
+
+        let provided = |secret: &Option<String>| secret.as_deref().is_some_and(|secret| !secret.is_empty());
+        let writes = [provided(&config.password), provided(&config.key_passphrase)];
+        config.store_secrets(&self.credential_store)?;
+
+        let written = [&config.password_ref, &config.key_passphrase_ref]
+            .into_iter()
+            .zip(writes)
+            .filter_map(|(credential, written)| credential.clone().filter(|_| written))
+            .map(|credential| {
+                let secret = replaced.iter().find(|(old, _)| *old == credential).and_then(|(_, secret)| secret.clone());
+                (credential, secret)
+            })
+            .collect();
+        Ok(written)
+    }
+
+    /// Puts back what `store_secrets` overwrote and removes what it added
+    fn restore_secrets(&self, written: Vec<(CredentialRef, Option<String>)>) {
+        for (credential, secret) in written {
+            let _ = match secret {
+                Some(secret) => self.credential_store.restore(&credential, &secret),
+                None => self.credential_store.delete(&credential),
+            };
+        }
+    }
+
+    /// Reads the file, upgrading older schemas in memory; a missing file is an empty inventory
+    fn load(&self) -> SSHResult<Vec<SSHConnectionConfig>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(SSHError::io("read", &self.path, e)),
+        };
+
+        let document: Value = serde_json::from_str(&contents)
+            .map_err(|e| SSHError::io("parse", &self.path, e))?;
+        let document = migrate(document)
+            .map_err(|e| SSHError::io("upgrade", &self.path, e))?;
+        let document: InventoryDocument = serde_json::from_value(document)
+            .map_err(|e| SSHError::io("parse", &self.path, e))?;
+
+        Ok(document.hosts)
+    }
+
+    /// Writes the whole inventory to a temporary file and renames it over the old one,
+    /// so a crash mid-write never leaves a truncated inventory behind
+    fn save(&self, hosts: &[SSHConnectionConfig]) -> SSHResult<()> {
+        let document = InventoryDocument {
+            version: SCHEMA_VERSION,
+            hosts: hosts.to_vec(),
+        };
+        let contents = serde_json::to_vec_pretty(&document)
+            .map_err(|e| SSHError::Internal(format!("Failed to serialize hosts: {}", e)))?;
+
+        let write_error = |e: std::io::Error| SSHError::io("write", &self.path, e);
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(write_error)?;
+        }
+
+        let mut temp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = self.path.with_file_name(temp_name);
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let written = options.open(&temp_path).and_then(|mut file| {
+            file.write_all(&contents)?;
+            file.sync_all()
+        });
+        if let Err(e) = written.and_then(|()| std::fs::rename(&temp_path, &self.path)) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(write_error(e));
+        }
+        Ok(())
+    }
+}
+
+/// Brings a document of any older schema up to `SCHEMA_VERSION`
+fn migrate(mut document: Value) -> Result<Value, String> {
+    let mut version = match &document {
+        Value::Array(_) => 0,
+        Value::Object(fields) => fields
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or("missing schema version")? as u32,
+        _ => return Err("expected an object or a list of hosts".to_string()),
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "schema {} was written by a newer version of Hana (this one understands up to {})",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    while version < SCHEMA_VERSION {
+        document = MIGRATIONS[version as usize](document)
+            .map_err(|e| format!("schema {} to {}: {}", version, version + 1, e))?;
+        version += 1;
+    }
+    Ok(document)
+}
+
+/// Checks a host definition, and that its jump hosts are other saved hosts
+fn validate(config: &SSHConnectionConfig, hosts: &[SSHConnectionConfig]) -> SSHResult<()> {
+    config.validate()?;
+
+    for jump_host in &config.jump_hosts {
+        if !hosts.iter().any(|host| &host.id == jump_host && host.id != config.id) {
+            return Err(SSHError::not_found(ResourceKind::JumpHost, jump_host));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::credentials::SecretKind;
+    use crate::ssh::types::AuthMethod;
+    use std::fs;
+
+    fn temp_inventory() -> HostInventory {
+        let path = std::env::temp_dir()
+            .join(format!("hana-inventory-{}", uuid::Uuid::new_v4()))
+            .join(INVENTORY_FILE_NAME);
+        HostInventory::new(path).with_credential_store(CredentialStore::in_memory())
+    }
+
+    fn host(name: &str) -> SSHConnectionConfig {
+        SSHConnectionConfig::new(
+            name.to_string(),
+            format!("{}.example.com", name),
+            22,
+            "deploy".to_string(),
+            vec![AuthMethod::Agent],
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_missing_file_is_empty() {
+        let inventory = temp_inventory();
+        assert!(inventory.list().await.unwrap().is_empty());
+        assert!(!inventory.path().exists());
+    }
+
+    #[tokio::test]
+    async fn test_create_update_delete_persist() {
+        let inventory = temp_inventory();
+
+        let web = inventory.create(host("web")).await.unwrap();
+        let db = inventory.create(host("db")).await.unwrap();
+
+        let mut renamed = web.clone();
+        renamed.name = "web-1".to_string();
+        renamed.port = 2222;
+        inventory.update(renamed).await.unwrap();
+        inventory.delete(&db.id).await.unwrap();
+
+        // A fresh instance sees exactly what was written
+        let reopened = HostInventory::new(inventory.path());
+        let hosts = reopened.list().await.unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].id, web.id);
+        assert_eq!(hosts[0].name, "web-1");
+        assert_eq!(hosts[0].port, 2222);
+
+        let document: Value = serde_json::from_str(&fs::read_to_string(inventory.path()).unwrap()).unwrap();
+        assert_eq!(document["version"], SCHEMA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_create_generates_id_and_rejects_duplicates() {
+        let inventory = temp_inventory();
+
+        let mut config = host("web");
+        config.id = String::new();
+        let created = inventory.create(config).await.unwrap();
+        assert!(!created.id.is_empty());
+
+        let result = inventory.create(created.clone()).await;
+        assert_eq!(result.unwrap_err().to_string(), format!("Host {} already exists", created.id));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_and_unknown_hosts_are_rejected() {
+        let inventory = temp_inventory();
+
+        let mut invalid = host("web");
+        invalid.hostname = String::new();
+        assert!(matches!(inventory.create(invalid).await, Err(SSHError::Validation(_))));
+
+        let missing = host("ghost");
+        let not_found = SSHError::not_found(ResourceKind::Host, &missing.id);
+        assert_eq!(inventory.update(missing.clone()).await.unwrap_err(), not_found);
+        assert_eq!(inventory.delete(&missing.id).await.unwrap_err(), not_found);
+        assert!(inventory.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_jump_hosts_must_exist_and_cannot_be_deleted() {
+        let inventory = temp_inventory();
+        let bastion = inventory.create(host("bastion")).await.unwrap();
+
+        let mut target = host("target");
+        target.jump_hosts = vec!["missing".to_string()];
+        assert_eq!(
+            inventory.create(target.clone()).await.unwrap_err(),
+            SSHError::not_found(ResourceKind::JumpHost, "missing")
+        );
+
+        target.jump_hosts = vec![bastion.id.clone()];
+        inventory.create(target).await.unwrap();
+
+        let result = inventory.delete(&bastion.id).await;
+        assert_eq!(result.unwrap_err().to_string(), "Host bastion is a jump host for target");
+    }
+
+    #[tokio::test]
+    async fn test_secrets_stay_in_the_keyring() {
+        let inventory = temp_inventory();
+        let store = inventory.credential_store.clone();
+
+        let mut config = host("web");
+        config.auth_methods = vec![AuthMethod::Password];
+        config.password = Some("s3cret".to_string());
+        let created = inventory.create(config).await.unwrap();
+
+        let password_ref = CredentialRef::for_connection(&created.id, SecretKind::Password);
+        assert_eq!(created.password_ref, Some(password_ref.clone()));
+        assert_eq!(store.load(&password_ref), Ok(Some("s3cret".to_string())));
+        assert!(!fs::read_to_string(inventory.path()).unwrap().contains("s3cret"));
+
+        // Switching away from password auth drops the stored password
+        let mut updated = created.clone();
+        updated.auth_methods = vec![AuthMethod::Agent];
+        updated.password_ref = None;
+        inventory.update(updated).await.unwrap();
+        assert_eq!(store.load(&password_ref), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_failed_save_leaves_the_keyring_as_it_was() {
+        let inventory = temp_inventory();
+        let store = inventory.credential_store.clone();
+
+        let mut config = host("web");
+        config.auth_methods = vec![AuthMethod::Password];
+        config.password = Some("old".to_string());
+        let created = inventory.create(config).await.unwrap();
+        let password_ref = created.password_ref.clone().unwrap();
+
+        // Nothing can be saved while a directory sits where the temporary file goes
+        fs::create_dir(inventory.path().with_file_name(format!("{}.tmp", INVENTORY_FILE_NAME))).unwrap();
+
+        let mut changed = created.clone();
+        changed.password = Some("new".to_string());
+        assert!(matches!(inventory.update(changed).await, Err(SSHError::Io { .. })));
+        assert_eq!(store.load(&password_ref), Ok(Some("old".to_string())));
+
+        let mut switched = created.clone();
+        switched.auth_methods = vec![AuthMethod::Agent];
+        switched.password_ref = None;
+        assert!(inventory.update(switched).await.is_err());
+        assert_eq!(store.load(&password_ref), Ok(Some("old".to_string())));
+
+        let mut other = host("db");
+        other.auth_methods = vec![AuthMethod::Password];
+        other.password = Some("s3cret".to_string());
+        assert!(inventory.create(other.clone()).await.is_err());
+        assert_eq!(store.load(&CredentialRef::for_connection(&other.id, SecretKind::Password)), Ok(None));
+
+        let hosts = inventory.list().await.unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].password_ref, Some(password_ref));
+        assert_eq!(hosts[0].auth_methods, vec![AuthMethod::Password]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_forgets_secrets() {
+        let inventory = temp_inventory();
+        let store = inventory.credential_store.clone();
+
+        let mut config = host("web");
+        config.auth_methods = vec![AuthMethod::Password];
+        config.password = Some("s3cret".to_string());
+        let created = inventory.create(config).await.unwrap();
+
+        inventory.delete(&created.id).await.unwrap();
+        assert_eq!(store.load(created.password_ref.as_ref().unwrap()), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_migrates_unversioned_file() {
+        let inventory = temp_inventory();
+        fs::create_dir_all(inventory.path().parent().unwrap()).unwrap();
+        fs::write(
+            inventory.path(),
+            r#"[{"id": "legacy", "name": "legacy", "hostname": "legacy.example.com", "port": 22,
+                 "username": "root", "auth_method": "Agent"}]"#,
+        )
+        .unwrap();
+
+        let hosts = inventory.list().await.unwrap();
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].auth_methods, vec![AuthMethod::Agent]);
+
+        // The next write stores the current schema
+        inventory.create(host("web")).await.unwrap();
+        let document: Value = serde_json::from_str(&fs::read_to_string(inventory.path()).unwrap()).unwrap();
+        assert_eq!(document["version"], SCHEMA_VERSION);
+        assert_eq!(document["hosts"].as_array().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_newer_schema_is_left_untouched() {
+        let inventory = temp_inventory();
+        fs::create_dir_all(inventory.path().parent().unwrap()).unwrap();
+        let newer = r#"{"version": 99, "hosts": []}"#;
+        fs::write(inventory.path(), newer).unwrap();
+
+        assert!(inventory.list().await.unwrap_err().to_string().contains("newer version of Hana"));
+        assert!(inventory.create(host("web")).await.is_err());
+        assert_eq!(fs::read_to_string(inventory.path()).unwrap(), newer);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_file_is_not_overwritten() {
+        let inventory = temp_inventory();
+        fs::create_dir_all(inventory.path().parent().unwrap()).unwrap();
+        fs::write(inventory.path(), "{ not json").unwrap();
+
+        let error = inventory.create(host("web")).await.unwrap_err();
+        assert!(matches!(error, SSHError::Io { ref operation, .. } if operation == "parse"));
+        assert_eq!(fs::read_to_string(inventory.path()).unwrap(), "{ not json");
+    }
+
+    #[tokio::test]
+    async fn test_save_leaves_no_temporary_file() {
+        let inventory = temp_inventory();
+        inventory.create(host("web")).await.unwrap();
+
+        let dir = inventory.path().parent().unwrap();
+        let names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec![INVENTORY_FILE_NAME.to_string()]);
+    }
+
+    #[test]
+    fn test_every_schema_has_a_migration() {
+        assert_eq!(MIGRATIONS.len(), SCHEMA_VERSION as usize);
+        assert!(migrate(serde_json::json!({ "version": 1, "hosts": [] })).is_ok());
+        assert!(migrate(serde_json::json!({ "hosts": [] })).unwrap_err().contains("missing schema version"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::ssh::error::{SSHError, SSHResult};
+use russh::keys::key::PublicKey;
+use russh::keys::{known_host_keys_path, learn_known_hosts_path};
+use std::path::{Path, PathBuf};
+
+/// Result of looking up a server key in a known_hosts file
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostKeyStatus {
+    /// The key matches an entry recorded for this host
+    Trusted,
+    /// No entry of this key type is recorded for this host
+    Unknown,
+    /// A different key of the same type is recorded for this host
+    Changed {
+        line: usize,
+        known_fingerprint: String,
+    },
+}
+
+/// An OpenSSH-format known_hosts file
+///
+/// Lookups understand plain and hashed (`|1|salt|hash`) host patterns as well as
+/// the `[host]:port` form used for non-standard ports.
+#[derive(Debug, Clone)]
+pub struct KnownHosts {
+    path: PathBuf,
+}
+
+impl KnownHosts {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Returns the user's default known_hosts location (`~/.ssh/known_hosts`)
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".ssh").join("known_hosts"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks the server key for the given host and port against the recorded entries
+    pub fn check(&self, hostname: &str, port: u16, key: &PublicKey) -> SSHResult<HostKeyStatus> {
+        let entries = known_host_keys_path(hostname, port, &self.path)
+            .map_err(|e| SSHError::io("read", &self.path, e))?;
+
+        if entries.iter().any(|(_, known)| known == key) {
+            return Ok(HostKeyStatus::Trusted);
+        }
+
+        // A host may legitimately have one key per algorithm, so only a
+        // mismatch within the same key type counts as a changed key
+        match entries.into_iter().find(|(_, known)| known.name() == key.name()) {
+            Some((line, known)) => Ok(HostKeyStatus::Changed {
+                line,
+                known_fingerprint: fingerprint(&known),
+            }),
+            None => Ok(HostKeyStatus::Unknown),
+        }
+    }
+
+    /// Appends an entry for the given host and port, creating the file if needed
+    pub fn learn(&self, hostname: &str, port: u16, key: &PublicKey) -> SSHResult<()> {
+        learn_known_hosts_path(hostname, port, key, &self.path)
+            .map_err(|e| SSHError::io("write", &self.path, e))
+    }
+}
+
+/// Formats a key fingerprint the way OpenSSH displays it
+pub fn fingerprint(key: &PublicKey) -> String {
+    format!("SHA256:{}", key.fingerprint())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use russh::keys::key::KeyPair;
+    use russh::keys::PublicKeyBase64;
+    use std::fs;
+
+    // Precomputed with salt 0x00..0x13 for "hashed.example.com" and "[hashed.example.com]:2222"
+    const HASHED_HOST: &str = "|1|AAECAwQFBgcICQoLDA0ODxAREhM=|tfTk2zfUwEOJq8/nQE8s/gLfc58=";
+    const HASHED_HOST_PORT: &str = "|1|AAECAwQFBgcICQoLDA0ODxAREhM=|XJLEd5NQ0siyJdQHu57Ej6vSlNI=";
+
+    fn temp_known_hosts(contents: &str) -> KnownHosts {
+        let path = std::env::temp_dir()
+            .join(format!("hana-known-hosts-{}", uuid::Uuid::new_v4()))
+            .join("known_hosts");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        KnownHosts::new(path)
+    }
+
+    fn generate_key() -> PublicKey {
+        KeyPair::generate_ed25519().unwrap().clone_public_key().unwrap()
+    }
+
+    fn entry(pattern: &str, key: &PublicKey) -> String {
+        format!("{} {} {}\n", pattern, key.name(), key.public_key_base64())
+    }
+
+    #[test]
+    fn test_missing_file_is_unknown() {
+        let known_hosts = KnownHosts::new(
+            std::env::temp_dir().join(format!("hana-missing-{}", uuid::Uuid::new_v4())),
+        );
+
+        let status = known_hosts.check("example.com", 22, &generate_key());
+        assert_eq!(status, Ok(HostKeyStatus::Unknown));
+    }
+
+    #[test]
+    fn test_plain_entry_trusted() {
+        let key = generate_key();
+        let known_hosts = temp_known_hosts(&entry("example.com", &key));
+
+        assert_eq!(known_hosts.check("example.com", 22, &key), Ok(HostKeyStatus::Trusted));
+        assert_eq!(known_hosts.check("other.com", 22, &key), Ok(HostKeyStatus::Unknown));
+        // Non-standard ports are recorded separately
+        assert_eq!(known_hosts.check("example.com", 2222, &key), Ok(HostKeyStatus::Unknown));
+    }
+
+    #[test]
+    fn test_bracketed_port_entry() {
+        let key = generate_key();
+        let known_hosts = temp_known_hosts(&entry("[example.com]:2222", &key));
+
+        assert_eq!(known_hosts.check("example.com", 2222, &key), Ok(HostKeyStatus::Trusted));
+        assert_eq!(known_hosts.check("example.com", 22, &key), Ok(HostKeyStatus::Unknown));
+    }
+
+    #[test]
+    fn test_hashed_entries() {
+        let key = generate_key();
+        let contents = format!("{}{}", entry(HASHED_HOST, &key), entry(HASHED_HOST_PORT, &key));
+        let known_hosts = temp_known_hosts(&contents);
+
+        assert_eq!(known_hosts.check("hashed.example.com", 22, &key), Ok(HostKeyStatus::Trusted));
+        assert_eq!(known_hosts.check("hashed.example.com", 2222, &key), Ok(HostKeyStatus::Trusted));
+        assert_eq!(known_hosts.check("other.example.com", 22, &key), Ok(HostKeyStatus::Unknown));
+    }
+
+    #[test]
+    fn test_changed_key_reports_known_fingerprint() {
+        let known_key = generate_key();
+        let presented_key = generate_key();
+        let contents = format!("# comment\n{}", entry("example.com", &known_key));
+        let known_hosts = temp_known_hosts(&contents);
+
+        match known_hosts.check("example.com", 22, &presented_key) {
+            Ok(HostKeyStatus::Changed { known_fingerprint, .. }) => {
+                assert_eq!(known_fingerprint, fingerprint(&known_key));
+                assert_ne!(known_fingerprint, fingerprint(&presented_key));
+            }
+            other => panic!("Expected changed key, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_learn_then_trusted() {
+        let key = generate_key();
+        let known_hosts = temp_known_hosts("");
+
+        known_hosts.learn("example.com", 2222, &key).unwrap();
+
+        let contents = fs::read_to_string(known_hosts.path()).unwrap();
+        assert!(contents.contains("[example.com]:2222 ssh-ed25519 "));
+        assert_eq!(known_hosts.check("example.com", 2222, &key), Ok(HostKeyStatus::Trusted));
+    }
+
+    #[test]
+    fn test_fingerprint_format() {
+        assert!(fingerprint(&generate_key()).starts_with("SHA256:"));
+    }
+}
@@ -1,15 +1,34 @@
 use crate::ssh::types::*;
 use crate::ssh::connection::SSHConnection;
+use crate::ssh::credentials::CredentialStore;
+use crate::ssh::error::{ResourceKind, SSHError, SSHResult};
+use crate::ssh::events::{self, EventSink};
+use crate::ssh::inventory::HostInventory;
+use crate::ssh::known_hosts::KnownHosts;
+use crate::ssh::terminal::TerminalSessionManager;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::sync::{mpsc, watch, Mutex, RwLock, Semaphore};
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
+/// Keyring namespace for secrets given with a live connection, kept apart from saved hosts'
+const CONNECTION_SECRETS_NAMESPACE: &str = "connection";
+
+#[derive(Clone)]
 pub struct SSHManager {
     connections: Arc<RwLock<HashMap<String, Arc<SSHConnection>>>>,
     connection_states: Arc<RwLock<HashMap<String, SSHConnectionState>>>,
+    /// Notified each time a connection's status is changed, so it can be waited on
+    status_changed: Arc<watch::Sender<()>>,
     event_sender: mpsc::Sender<SSHEvent>,
     event_receiver: Arc<Mutex<mpsc::Receiver<SSHEvent>>>,
+    credential_store: CredentialStore,
+    known_hosts: Option<KnownHosts>,
+    /// Saved hosts, which jump hosts and batch targets are looked up in
+    inventory: Option<Arc<HostInventory>>,
+    /// Shared by every connection, so broadcast groups can span hosts
+    terminal_manager: Arc<TerminalSessionManager>,
 }
 
 impl SSHManager {
@@ -19,26 +38,67 @@ impl SSHManager {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             connection_states: Arc::new(RwLock::new(HashMap::new())),
+            status_changed: Arc::new(watch::channel(()).0),
+            terminal_manager: Arc::new(TerminalSessionManager::new(event_sender.clone())),
             event_sender,
             event_receiver: Arc::new(Mutex::new(event_receiver)),
+            credential_store: CredentialStore::system(),
+            known_hosts: None,
+            inventory: None,
         }
     }
 
-    pub async fn create_connection(&self, mut config: SSHConnectionConfig) -> Result<String, String> {
+    /// Keeps connection secrets in the given keyring instead of the platform one
+    pub fn with_credential_store(mut self, credential_store: CredentialStore) -> Self {
+        self.credential_store = credential_store;
+        self
+    }
+
+    /// Verifies host keys against the given known_hosts file instead of the user's default one
+    pub fn with_known_hosts(mut self, known_hosts: KnownHosts) -> Self {
+        self.known_hosts = Some(known_hosts);
+        self
+    }
+
+    /// Resolves jump hosts and batch targets from the given saved hosts as well as from
+    /// live connections
+    pub fn with_inventory(mut self, inventory: Arc<HostInventory>) -> Self {
+        self.inventory = Some(inventory);
+        self
+    }
+
+    pub async fn create_connection(&self, config: SSHConnectionConfig) -> SSHResult<String> {
+        let connection = self.add_connection(config, ConnectionStatus::Connecting).await?;
+        let connection_id = connection.id.clone();
+
+        // Attempt to connect in background
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let _ = manager.establish(connection).await;
+        });
+
+        Ok(connection_id)
+    }
+
+    /// Registers a connection in the given status without connecting it
+    async fn add_connection(&self, mut config: SSHConnectionConfig, status: ConnectionStatus) -> SSHResult<Arc<SSHConnection>> {
         // Validate configuration first
-        config.validate().map_err(|errors| {
-            format!("Configuration validation failed: {}", 
-                errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", "))
-        })?;
+        config.validate()?;
 
         // Generate ID if not provided
         if config.id.is_empty() {
             config.id = Uuid::new_v4().to_string();
         }
 
+        // Jump hosts must be saved hosts or existing connections
+        let jump_hosts = self.resolve_jump_hosts(&config).await?;
+
+        // Keep secrets in the keyring; the stored config only references them
+        config.store_secrets(&self.connection_secrets())?;
+
         // Create connection state
         let mut state = SSHConnectionState::new(config.id.clone());
-        state.update_status(ConnectionStatus::Connecting);
+        state.update_status(status);
 
         // Store initial state
         {
@@ -47,7 +107,14 @@ impl SSHManager {
         }
 
         // Create connection
-        let connection = Arc::new(SSHConnection::new(config.clone(), self.event_sender.clone()));
+        let mut connection = SSHConnection::new(config.clone(), self.event_sender.clone())
+            .with_credential_store(self.credential_store.clone())
+            .with_terminal_manager(self.terminal_manager.clone())
+            .with_jump_hosts(jump_hosts);
+        if let Some(known_hosts) = &self.known_hosts {
+            connection = connection.with_known_hosts(known_hosts.clone());
+        }
+        let connection = Arc::new(connection);
         
         // Store connection
         {
@@ -55,37 +122,11 @@ impl SSHManager {
             connections.insert(config.id.clone(), connection.clone());
         }
 
-        // Attempt to connect in background
-        let connection_clone = connection.clone();
-        let connection_id = config.id.clone();
-        let states_clone = self.connection_states.clone();
-        
-        tokio::spawn(async move {
-            match connection_clone.connect().await {
-                Ok(()) => {
-                    let mut states = states_clone.write().await;
-                    if let Some(state) = states.get_mut(&connection_id) {
-                        state.update_status(ConnectionStatus::Connected);
-                    }
-                }
-                Err(error) => {
-                    let mut states = states_clone.write().await;
-                    if let Some(state) = states.get_mut(&connection_id) {
-                        state.set_error(error);
-                    }
-                }
-            }
-        });
-        
-        Ok(config.id)
+        Ok(connection)
     }
 
-    pub async fn connect_existing(&self, connection_id: &str) -> Result<(), String> {
-        let connection = {
-            let connections = self.connections.read().await;
-            connections.get(connection_id).cloned()
-                .ok_or_else(|| format!("Connection {} not found", connection_id))?
-        };
+    pub async fn connect_existing(&self, connection_id: &str) -> SSHResult<()> {
+        let connection = self.connection(connection_id).await?;
 
         // Update state to connecting
         {
@@ -94,32 +135,141 @@ impl SSHManager {
                 state.update_status(ConnectionStatus::Connecting);
             }
         }
+        self.status_changed.send_replace(());
 
         // Attempt connection
-        match connection.connect().await {
-            Ok(()) => {
-                let mut states = self.connection_states.write().await;
-                if let Some(state) = states.get_mut(connection_id) {
+        self.establish(connection).await
+    }
+
+    /// Connects and records the outcome; once connected, the session is watched so it
+    /// can be re-established if it drops
+    async fn establish(&self, connection: Arc<SSHConnection>) -> SSHResult<()> {
+        let result = connection.connect().await;
+        self.record_connect_result(&connection, &result).await;
+        if result.is_ok() {
+            // Terminals left detached by an earlier drop get their shells back
+            connection.reattach_terminal_sessions().await;
+            self.supervise(connection);
+        }
+        result
+    }
+
+    /// Records the outcome of a connection attempt in the connection's state
+    async fn record_connect_result(&self, connection: &SSHConnection, result: &SSHResult<()>) {
+        let auth_report = connection.auth_report().await;
+        let auth_identity = connection.auth_identity().await;
+        let mut states = self.connection_states.write().await;
+        if let Some(state) = states.get_mut(&connection.id) {
+            match result {
+                Ok(()) => {
                     state.update_status(ConnectionStatus::Connected);
+                    state.auth_identity = auth_identity;
                 }
-                Ok(())
+                Err(error) => state.set_error(error.clone()),
             }
-            Err(error) => {
-                let mut states = self.connection_states.write().await;
-                if let Some(state) = states.get_mut(connection_id) {
-                    state.set_error(error.clone());
+            state.auth_report = auth_report;
+        }
+        drop(states);
+        self.status_changed.send_replace(());
+    }
+
+    /// Watches a connected session and re-establishes it each time it drops, until it is
+    /// closed on purpose or reconnecting gives up
+    fn supervise(&self, connection: Arc<SSHConnection>) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            while let Some(error) = connection.session_lost().await {
+                if !manager.reconnect(&connection, error).await {
+                    break;
                 }
-                Err(error)
             }
+        });
+    }
+
+    /// Reports why a connection dropped, then reconnects it following its host's reconnect
+    /// policy and restarts its terminal sessions under their old ids. Returns false if it
+    /// gave up, or if the connection was disconnected or removed in the meantime.
+    async fn reconnect(&self, connection: &Arc<SSHConnection>, mut error: SSHError) -> bool {
+        let _ = self.event_sender.send(SSHEvent::Error(connection.id.clone(), error.clone())).await;
+
+        let policy = &connection.config.reconnect;
+        let attempts = if policy.enabled { policy.max_attempts } else { 0 };
+
+        for attempt in 1..=attempts {
+            let delay = policy.next_delay(attempt);
+            if !self.set_reconnecting(connection, attempt).await {
+                return false;
+            }
+            let _ = self.event_sender
+                .send(SSHEvent::Reconnecting(connection.id.clone(), attempt, delay.as_millis() as u64))
+                .await;
+            tokio::time::sleep(delay).await;
+            if !self.is_reconnecting(connection).await {
+                return false;
+            }
+
+            let result = connection.connect().await;
+            if !self.is_reconnecting(connection).await {
+                // Disconnected while the attempt was under way
+                if result.is_ok() {
+                    let _ = connection.disconnect().await;
+                }
+                return false;
+            }
+            match result {
+                Ok(()) => {
+                    self.record_connect_result(connection, &result).await;
+                    connection.reattach_terminal_sessions().await;
+                    return true;
+                }
+                // Authentication and host key failures won't go away by themselves
+                Err(e) if !e.is_retryable() => {
+                    error = e;
+                    break;
+                }
+                Err(e) => error = e,
+            }
+        }
+
+        let _ = connection.disconnect().await;
+        self.record_connect_result(connection, &Err(error)).await;
+        false
+    }
+
+    /// Moves a dropped or still reconnecting connection on to the given attempt; returns
+    /// false if it was disconnected or removed meanwhile
+    async fn set_reconnecting(&self, connection: &Arc<SSHConnection>, attempt: u32) -> bool {
+        if !self.is_registered(connection).await {
+            return false;
+        }
+        let mut states = self.connection_states.write().await;
+        match states.get_mut(&connection.id) {
+            Some(state) if matches!(state.status, ConnectionStatus::Connected | ConnectionStatus::Reconnecting { .. }) => {
+                state.update_status(ConnectionStatus::Reconnecting { attempt });
+                drop(states);
+                self.status_changed.send_replace(());
+                true
+            }
+            _ => false,
         }
     }
 
-    pub async fn disconnect_connection(&self, connection_id: &str) -> Result<(), String> {
-        let connection = {
-            let connections = self.connections.read().await;
-            connections.get(connection_id).cloned()
-                .ok_or_else(|| format!("Connection {} not found", connection_id))?
-        };
+    async fn is_reconnecting(&self, connection: &Arc<SSHConnection>) -> bool {
+        let reconnecting = self.connection_states.read().await
+            .get(&connection.id)
+            .is_some_and(|state| matches!(state.status, ConnectionStatus::Reconnecting { .. }));
+        reconnecting && self.is_registered(connection).await
+    }
+
+    /// Whether the connection is still the one registered under its id
+    async fn is_registered(&self, connection: &Arc<SSHConnection>) -> bool {
+        self.connections.read().await
+            .get(&connection.id)
+            .is_some_and(|registered| Arc::ptr_eq(registered, connection))
+    }
+
+    pub async fn disconnect_connection(&self, connection_id: &str) -> SSHResult<()> {
+        let connection = self.connection(connection_id).await?;
 
         // Disconnect
         connection.disconnect().await?;
@@ -129,36 +279,393 @@ impl SSHManager {
             let mut states = self.connection_states.write().await;
             if let Some(state) = states.get_mut(connection_id) {
                 state.update_status(ConnectionStatus::Disconnected);
+                state.auth_identity = None;
+                state.auth_report = AuthReport::default();
             }
         }
+        self.status_changed.send_replace(());
 
         Ok(())
     }
 
+    /// Accepts or rejects the unknown host key a connection is waiting on
+    pub async fn respond_to_host_key_prompt(&self, connection_id: &str, accept: bool) -> SSHResult<()> {
+        let connection = self.connection(connection_id).await?;
+        connection.respond_to_host_key_prompt(accept).await
+    }
+
+    /// Supplies the private key passphrase a connection is waiting on
+    pub async fn submit_key_passphrase(&self, connection_id: &str, passphrase: Option<String>) -> SSHResult<()> {
+        let connection = self.connection(connection_id).await?;
+        connection.submit_key_passphrase(passphrase).await
+    }
+
+    /// Answers the keyboard-interactive challenge a connection is waiting on
+    pub async fn submit_keyboard_interactive_responses(
+        &self,
+        connection_id: &str,
+        responses: Option<Vec<String>>,
+    ) -> SSHResult<()> {
+        let connection = self.connection(connection_id).await?;
+        connection.submit_keyboard_interactive_responses(responses).await
+    }
+
+    /// Returns the full chain of jump hosts a connection tunnels through, outermost first.
+    /// A jump host's own jump hosts come before it, as when they are nested in OpenSSH.
+    async fn resolve_jump_hosts(&self, config: &SSHConnectionConfig) -> SSHResult<Vec<SSHConnectionConfig>> {
+        // Look up every host reachable from the connection before walking the chain
+        let mut jump_configs = HashMap::new();
+        let mut pending = config.jump_hosts.clone();
+        while let Some(jump_host) = pending.pop() {
+            if jump_host == config.id || jump_configs.contains_key(&jump_host) {
+                continue;
+            }
+            let jump_config = self.jump_host_config(&jump_host).await?;
+            pending.extend(jump_config.jump_hosts.iter().cloned());
+            jump_configs.insert(jump_host, jump_config);
+        }
+
+        let mut chain = Vec::new();
+        flatten_jump_hosts(config, &jump_configs, &mut vec![config.id.clone()], &mut chain)?;
+        Ok(chain)
+    }
+
+    /// Looks up a jump host in the saved hosts, then in the live connections
+    async fn jump_host_config(&self, jump_host: &str) -> SSHResult<SSHConnectionConfig> {
+        if let Some(inventory) = &self.inventory {
+            match inventory.get(jump_host).await {
+                Ok(config) => return Ok(config),
+                Err(SSHError::NotFound { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let connections = self.connections.read().await;
+        connections.get(jump_host)
+            .map(|connection| connection.config.clone())
+            .ok_or_else(|| SSHError::not_found(ResourceKind::JumpHost, jump_host))
+    }
+
     pub async fn get_connection(&self, connection_id: &str) -> Option<Arc<SSHConnection>> {
         let connections = self.connections.read().await;
         connections.get(connection_id).cloned()
     }
 
+    /// Like `get_connection`, but a missing connection is an error
+    async fn connection(&self, connection_id: &str) -> SSHResult<Arc<SSHConnection>> {
+        self.get_connection(connection_id).await
+            .ok_or_else(|| SSHError::not_found(ResourceKind::Connection, connection_id))
+    }
+
+    /// Opens a terminal session running the user's shell on a connection
+    pub async fn create_terminal_session(
+        &self,
+        connection_id: &str,
+        size: Option<TerminalSize>,
+        term: Option<String>,
+    ) -> SSHResult<String> {
+        self.connection(connection_id).await?
+            .create_terminal_session(size, term)
+            .await
+    }
+
+    /// Sends input to one of a connection's terminal sessions
+    pub async fn send_terminal_input(&self, connection_id: &str, terminal_id: &str, data: &[u8]) -> SSHResult<()> {
+        self.connection(connection_id).await?
+            .send_terminal_input(terminal_id, data)
+            .await
+    }
+
+    /// Resizes one of a connection's terminal sessions
+    pub async fn resize_terminal_session(&self, connection_id: &str, terminal_id: &str, size: TerminalSize) -> SSHResult<()> {
+        self.connection(connection_id).await?
+            .resize_terminal(terminal_id, size.cols, size.rows, size.pixel_width, size.pixel_height)
+            .await
+    }
+
+    /// Closes one of a connection's terminal sessions
+    pub async fn close_terminal_session(&self, connection_id: &str, terminal_id: &str) -> SSHResult<()> {
+        self.connection(connection_id).await?
+            .close_terminal_session(terminal_id)
+            .await
+    }
+
+    pub async fn get_terminal_session(&self, connection_id: &str, terminal_id: &str) -> SSHResult<TerminalSession> {
+        self.connection(connection_id).await?
+            .get_terminal_session(terminal_id)
+            .await
+            .ok_or_else(|| SSHError::not_found(ResourceKind::TerminalSession, terminal_id))
+    }
+
+    /// Returns what a terminal session has printed, within its scrollback limits
+    pub async fn replay_terminal_session(&self, connection_id: &str, terminal_id: &str) -> SSHResult<TerminalReplay> {
+        self.connection(connection_id).await?
+            .replay_terminal(terminal_id)
+            .await
+    }
+
+    pub async fn list_terminal_sessions(&self, connection_id: &str) -> SSHResult<Vec<TerminalSession>> {
+        Ok(self.connection(connection_id).await?.list_terminal_sessions().await)
+    }
+
+    /// Groups terminal sessions, on any connections, so input can be sent to all of them at once
+    pub async fn create_broadcast_group(&self, name: String, terminal_ids: Vec<String>) -> SSHResult<BroadcastGroup> {
+        self.terminal_manager.create_broadcast_group(name, terminal_ids).await
+    }
+
+    pub async fn join_broadcast_group(&self, group_id: &str, terminal_id: &str) -> SSHResult<BroadcastGroup> {
+        self.terminal_manager.join_broadcast_group(group_id, terminal_id).await
+    }
+
+    pub async fn leave_broadcast_group(&self, group_id: &str, terminal_id: &str) -> SSHResult<BroadcastGroup> {
+        self.terminal_manager.leave_broadcast_group(group_id, terminal_id).await
+    }
+
+    pub async fn delete_broadcast_group(&self, group_id: &str) -> SSHResult<()> {
+        self.terminal_manager.delete_broadcast_group(group_id).await
+    }
+
+    pub async fn list_broadcast_groups(&self) -> Vec<BroadcastGroup> {
+        self.terminal_manager.list_broadcast_groups().await
+    }
+
+    /// Sends input to every terminal in a broadcast group, reporting each one's outcome
+    pub async fn broadcast_terminal_input(&self, group_id: &str, data: &[u8]) -> SSHResult<BroadcastResult> {
+        self.terminal_manager.broadcast_input(group_id, data).await
+    }
+
+    /// Runs a one-off command on a connection
+    pub async fn exec(&self, connection_id: &str, request: ExecRequest) -> SSHResult<ExecResult> {
+        self.connection(connection_id).await?.exec(request).await
+    }
+
+    /// Runs a command on every targeted connection, at most `max_concurrency` at a time,
+    /// connecting the ones that are down and opening one for each targeted saved host
+    /// that has none. Each host's progress is sent on the batch's
+    /// event; a host that can't be reached or run the command only fails its own row.
+    pub async fn run_batch(&self, request: BatchRequest) -> SSHResult<BatchResult> {
+        request.validate()?;
+
+        let started = tokio::time::Instant::now();
+        let batch_id = request.batch_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string());
+        let connection_ids = self.batch_targets(&request.targets).await?;
+        let total = connection_ids.len();
+
+        let permits = Arc::new(Semaphore::new(request.max_concurrency()));
+        let mut tasks = JoinSet::new();
+        // Each task's row and host, so a task that panics still gets its row
+        let mut rows = HashMap::new();
+        for (index, connection_id) in connection_ids.into_iter().enumerate() {
+            let manager = self.clone();
+            let permits = permits.clone();
+            let batch_id = batch_id.clone();
+            let exec = request.exec.clone();
+            let host_id = connection_id.clone();
+            let task = tasks.spawn(async move {
+                // The semaphore is never closed, so acquiring only waits
+                let _permit = permits.acquire_owned().await;
+                let _ = manager.event_sender
+                    .send(SSHEvent::BatchHostStarted(batch_id, connection_id.clone()))
+                    .await;
+                manager.run_batch_host(connection_id, exec).await
+            });
+            rows.insert(task.id(), (index, host_id));
+        }
+
+        let mut hosts = vec![None; total];
+        let mut finished = 0;
+        while let Some(joined) = tasks.join_next_with_id().await {
+            let (task_id, host) = match joined {
+                Ok(joined) => joined,
+                Err(error) => {
+                    let task_id = error.id();
+                    let connection_id = rows[&task_id].1.clone();
+                    let name = self.get_connection(&connection_id).await.map(|connection| connection.config.name.clone());
+                    let error = SSHError::Internal(format!("Running the command on {} failed: {}", connection_id, error));
+                    (task_id, BatchHostResult { connection_id, name, result: None, error: Some(error) })
+                }
+            };
+            let index = rows[&task_id].0;
+            finished += 1;
+            let _ = self.event_sender
+                .send(SSHEvent::BatchHostFinished(batch_id.clone(), finished, total, Box::new(host.clone())))
+                .await;
+            hosts[index] = Some(host);
+        }
+
+        let hosts: Vec<BatchHostResult> = hosts.into_iter().flatten().collect();
+        let succeeded = hosts.iter().filter(|host| host.succeeded()).count();
+        Ok(BatchResult {
+            batch_id,
+            command: request.exec.command,
+            failed: hosts.len() - succeeded,
+            succeeded,
+            hosts,
+            duration_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// The connections a batch runs on, each once. Tags are matched against live connections
+    /// and saved hosts, a saved host standing for the connection under its id, and the
+    /// matches are ordered by name.
+    async fn batch_targets(&self, targets: &BatchTargets) -> SSHResult<Vec<String>> {
+        let targets = match targets {
+            BatchTargets::Connections(connection_ids) => {
+                let mut unique: Vec<String> = Vec::new();
+                for connection_id in connection_ids {
+                    if !unique.contains(connection_id) {
+                        unique.push(connection_id.clone());
+                    }
+                }
+                unique
+            }
+            BatchTargets::Tags(tags) => {
+                let saved = match &self.inventory {
+                    Some(inventory) => inventory.list().await?,
+                    None => Vec::new(),
+                };
+                let connections = self.connections.read().await;
+                let mut tagged: Vec<(String, String)> = connections
+                    .values()
+                    .map(|connection| &connection.config)
+                    .chain(saved.iter().filter(|host| !connections.contains_key(&host.id)))
+                    .filter(|config| config.has_tags(tags))
+                    .map(|config| (config.name.clone(), config.id.clone()))
+                    .collect();
+                tagged.sort();
+                tagged.into_iter().map(|(_, id)| id).collect()
+            }
+        };
+        Ok(targets)
+    }
+
+    /// Runs a batch's command on one connection, connecting it first if it is down
+    async fn run_batch_host(&self, connection_id: String, request: ExecRequest) -> BatchHostResult {
+        let connection = self.batch_connection(&connection_id).await;
+        let name = connection.as_ref().ok().map(|connection| connection.config.name.clone());
+        let outcome = match connection {
+            Ok(_) => match self.ensure_connected(&connection_id).await {
+                Ok(()) => self.exec(&connection_id, request).await,
+                Err(error) => Err(error),
+            },
+            Err(error) => Err(error),
+        };
+
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        BatchHostResult { connection_id, name, result, error }
+    }
+
+    /// The connection a batch runs on. A saved host with no connection yet gets one, left
+    /// disconnected for `ensure_connected` to bring up.
+    async fn batch_connection(&self, connection_id: &str) -> SSHResult<Arc<SSHConnection>> {
+        if let Some(connection) = self.get_connection(connection_id).await {
+            return Ok(connection);
+        }
+        let missing = || SSHError::not_found(ResourceKind::Connection, connection_id);
+        let inventory = self.inventory.as_ref().ok_or_else(missing)?;
+        match inventory.get(connection_id).await {
+            Ok(host) => self.add_connection(host, ConnectionStatus::Disconnected).await,
+            Err(SSHError::NotFound { .. }) => Err(missing()),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Connects a connection that is down. One that is already connecting or reconnecting
+    /// is given its connect timeout to finish rather than being connected a second time.
+    async fn ensure_connected(&self, connection_id: &str) -> SSHResult<()> {
+        let connection = self.connection(connection_id).await?;
+        let status = || async {
+            self.get_connection_state(connection_id).await.map(|state| state.status)
+        };
+
+        // Subscribed before the status is first read, so a change in between isn't missed
+        let mut status_changed = self.status_changed.subscribe();
+        let _ = tokio::time::timeout(connection.config.connect_timeout(), async {
+            while matches!(status().await, Some(ConnectionStatus::Connecting | ConnectionStatus::Reconnecting { .. })) {
+                if status_changed.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+        .await;
+
+        if connection.is_connected().await {
+            return Ok(());
+        }
+        match status().await {
+            Some(ConnectionStatus::Disconnected | ConnectionStatus::Error) => self.connect_existing(connection_id).await,
+            _ => Err(SSHError::NotConnected),
+        }
+    }
+
+    /// Starts a local port forward on a connection
+    pub async fn create_local_forward(&self, connection_id: &str, rule: LocalForward) -> SSHResult<PortForward> {
+        self.connection(connection_id).await?
+            .create_local_forward(rule)
+            .await
+    }
+
+    /// Starts a remote port forward on a connection
+    pub async fn create_remote_forward(&self, connection_id: &str, rule: RemoteForward) -> SSHResult<PortForward> {
+        self.connection(connection_id).await?
+            .create_remote_forward(rule)
+            .await
+    }
+
+    /// Starts a SOCKS proxy on a connection
+    pub async fn create_dynamic_forward(&self, connection_id: &str, rule: DynamicForward) -> SSHResult<PortForward> {
+        self.connection(connection_id).await?
+            .create_dynamic_forward(rule)
+            .await
+    }
+
+    /// Lists a connection's running port forwards with their traffic so far
+    pub async fn list_port_forwards(&self, connection_id: &str) -> SSHResult<Vec<PortForward>> {
+        Ok(self.connection(connection_id).await?.list_port_forwards().await)
+    }
+
+    /// Stops one of a connection's port forwards
+    pub async fn stop_port_forward(&self, connection_id: &str, forward_id: &str) -> SSHResult<()> {
+        self.connection(connection_id).await?
+            .stop_port_forward(forward_id)
+            .await
+    }
+
     pub async fn get_connection_state(&self, connection_id: &str) -> Option<SSHConnectionState> {
         let states = self.connection_states.read().await;
         states.get(connection_id).cloned()
     }
 
-    pub async fn remove_connection(&self, connection_id: &str) -> Result<(), String> {
+    pub async fn remove_connection(&self, connection_id: &str) -> SSHResult<()> {
         // First disconnect if connected
-        if let Some(connection) = self.get_connection(connection_id).await {
+        let connection = self.get_connection(connection_id).await;
+        if let Some(connection) = &connection {
             let _ = connection.disconnect().await; // Ignore errors during cleanup
         }
 
         // Remove from both maps
-        let mut connections = self.connections.write().await;
-        let mut states = self.connection_states.write().await;
-        
-        connections.remove(connection_id);
-        states.remove(connection_id);
-        
-        Ok(())
+        {
+            let mut connections = self.connections.write().await;
+            let mut states = self.connection_states.write().await;
+
+            connections.remove(connection_id);
+            states.remove(connection_id);
+        }
+        self.status_changed.send_replace(());
+
+        // Drop the connection's secrets from the keyring
+        match connection {
+            Some(connection) => connection.config.clone().forget_secrets(&self.connection_secrets()),
+            None => Ok(()),
+        }
+    }
+
+    /// The part of the keyring that holds secrets given with live connections. Secrets a
+    /// connection references from a saved host belong to the inventory and are never removed here.
+    fn connection_secrets(&self) -> CredentialStore {
+        self.credential_store.namespaced(CONNECTION_SECRETS_NAMESPACE)
     }
 
     pub async fn list_connections(&self) -> Vec<String> {
@@ -180,12 +687,936 @@ impl SSHManager {
     }
 
     // Event handling methods
+
+    /// The receiving end of this manager's events. It has a single consumer: once
+    /// `dispatch_events` is running it holds the lock for good, so this is only for
+    /// callers that read events themselves instead.
     pub async fn get_event_receiver(&self) -> Arc<Mutex<mpsc::Receiver<SSHEvent>>> {
         self.event_receiver.clone()
     }
 
-    pub async fn send_event(&self, event: SSHEvent) -> Result<(), String> {
+    /// Forwards this manager's events to the frontend for as long as the manager lives
+    pub async fn dispatch_events<S: EventSink>(&self, sink: S) {
+        events::dispatch_events(self.event_receiver.clone(), sink).await
+    }
+
+    pub async fn send_event(&self, event: SSHEvent) -> SSHResult<()> {
         self.event_sender.send(event).await
-            .map_err(|e| format!("Failed to send event: {}", e))
+            .map_err(|e| SSHError::Internal(format!("Failed to send event: {}", e)))
+    }
+}
+
+/// Appends the jump hosts of `config` to `chain`, each preceded by its own jump hosts.
+/// `path` holds the hosts being walked through, so a chain that loops back is an error.
+fn flatten_jump_hosts(
+    config: &SSHConnectionConfig,
+    jump_configs: &HashMap<String, SSHConnectionConfig>,
+    path: &mut Vec<String>,
+    chain: &mut Vec<SSHConnectionConfig>,
+) -> SSHResult<()> {
+    for jump_host in &config.jump_hosts {
+        if path.contains(jump_host) {
+            return Err(SSHError::InvalidState(format!(
+                "Jump hosts for {} form a loop ({} -> {})",
+                path[0], path.join(" -> "), jump_host
+            )));
+        }
+        let jump_config = &jump_configs[jump_host];
+        path.push(jump_host.clone());
+        flatten_jump_hosts(jump_config, jump_configs, path, chain)?;
+        path.pop();
+        chain.push(jump_config.clone());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::test_server::*;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+    use tokio::time::{sleep, timeout, Duration};
+
+    fn password_config(addr: SocketAddr) -> SSHConnectionConfig {
+        SSHConnectionConfig {
+            id: String::new(),
+            name: "Test Server".to_string(),
+            hostname: addr.ip().to_string(),
+            port: addr.port(),
+            username: TEST_USERNAME.to_string(),
+            auth_methods: vec![AuthMethod::Password],
+            private_key_path: None,
+            password: Some(TEST_PASSWORD.to_string()),
+            // Long enough for the tests to see the status change, short enough not to wait on
+            reconnect: ReconnectPolicy {
+                initial_delay_ms: 200,
+                max_delay_ms: 200,
+                jitter: 0.0,
+                ..ReconnectPolicy::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn wait_for_status(manager: &SSHManager, connection_id: &str, status: ConnectionStatus) {
+        timeout(Duration::from_secs(5), async {
+            while manager.get_connection_state(connection_id).await.map(|state| state.status) != Some(status.clone()) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("connection never reached the expected status");
     }
-}
\ No newline at end of file
+
+    /// Returns a manager with one connection to a test server running the test shell
+    async fn connect_to_shell_server() -> (TestServer, SSHManager, String) {
+        let server = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let manager = SSHManager::new()
+            .with_credential_store(CredentialStore::in_memory())
+            .with_known_hosts(server.known_hosts());
+
+        let connection_id = manager.create_connection(password_config(server.addr)).await.unwrap();
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+        (server, manager, connection_id)
+    }
+
+    /// Waits for the next output event for a terminal, skipping any other events
+    async fn next_output(manager: &SSHManager, terminal_id: &str) -> Vec<u8> {
+        let receiver = manager.get_event_receiver().await;
+        let mut receiver = receiver.lock().await;
+        timeout(Duration::from_secs(5), async {
+            loop {
+                match receiver.recv().await {
+                    Some(SSHEvent::Data(_, id, _, _, data)) if id == terminal_id => return data,
+                    Some(_) => continue,
+                    None => panic!("event channel closed"),
+                }
+            }
+        })
+        .await
+        .expect("no output arrived")
+    }
+
+    #[tokio::test]
+    async fn test_hung_connect_does_not_block_other_connections() {
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+
+        // Accepts the TCP connection but never starts the SSH handshake
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        let _held = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                streams.push(stream);
+            }
+        });
+        // Connecting carries on in the background, stuck waiting for the handshake
+        let hung_id = manager.create_connection(password_config(silent_addr)).await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+
+        let sent = timeout(
+            Duration::from_secs(5),
+            manager.send_terminal_input(&connection_id, &terminal_id, b"hello\n"),
+        )
+        .await;
+        assert_eq!(sent.expect("input was blocked by the hung connect"), Ok(()));
+        assert_eq!(
+            manager.get_connection_state(&hung_id).await.unwrap().status,
+            ConnectionStatus::Connecting
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_connection_is_not_found() {
+        let manager = SSHManager::new();
+        let not_found = SSHError::not_found(ResourceKind::Connection, "missing");
+
+        assert_eq!(manager.connect_existing("missing").await, Err(not_found.clone()));
+        assert_eq!(manager.create_terminal_session("missing", None, None).await, Err(not_found.clone()));
+        assert_eq!(manager.send_terminal_input("missing", "terminal", b"x").await, Err(not_found.clone()));
+        assert_eq!(
+            manager.resize_terminal_session("missing", "terminal", TerminalSize::default()).await,
+            Err(not_found.clone())
+        );
+        assert_eq!(manager.close_terminal_session("missing", "terminal").await, Err(not_found.clone()));
+        assert_eq!(manager.get_terminal_session("missing", "terminal").await.unwrap_err(), not_found);
+        assert_eq!(manager.list_terminal_sessions("missing").await.unwrap_err(), not_found);
+    }
+
+    /// Returns an empty inventory in a fresh temporary directory
+    fn temp_inventory(credential_store: CredentialStore) -> Arc<HostInventory> {
+        let path = std::env::temp_dir()
+            .join(format!("hana-manager-{}", Uuid::new_v4()))
+            .join(crate::ssh::inventory::INVENTORY_FILE_NAME);
+        Arc::new(HostInventory::new(path).with_credential_store(credential_store))
+    }
+
+    #[tokio::test]
+    async fn test_jump_hosts_are_resolved_through_saved_hosts() {
+        let outer = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let inner = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let target = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let known_hosts = outer.known_hosts();
+        for server in [&inner, &target] {
+            known_hosts.learn(&server.addr.ip().to_string(), server.addr.port(), &server.host_key).unwrap();
+        }
+
+        // The target names only the inner host, which is itself reached through the outer one
+        let credential_store = CredentialStore::in_memory();
+        let inventory = temp_inventory(credential_store.clone());
+        let outer_host = inventory.create(password_config(outer.addr)).await.unwrap();
+        let inner_host = inventory.create(SSHConnectionConfig {
+            jump_hosts: vec![outer_host.id.clone()],
+            ..password_config(inner.addr)
+        }).await.unwrap();
+
+        let manager = SSHManager::new()
+            .with_credential_store(credential_store)
+            .with_known_hosts(known_hosts)
+            .with_inventory(inventory);
+        let connection_id = manager.create_connection(SSHConnectionConfig {
+            jump_hosts: vec![inner_host.id.clone()],
+            ..password_config(target.addr)
+        }).await.unwrap();
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+
+        assert_eq!(*outer.tunnels.lock().unwrap(), vec![inner.addr]);
+        assert_eq!(*inner.tunnels.lock().unwrap(), vec![target.addr]);
+    }
+
+    #[tokio::test]
+    async fn test_jump_host_loop_is_rejected() {
+        let credential_store = CredentialStore::in_memory();
+        let inventory = temp_inventory(credential_store.clone());
+        let addr: SocketAddr = "127.0.0.1:22".parse().unwrap();
+        let first = inventory.create(password_config(addr)).await.unwrap();
+        let second = inventory.create(SSHConnectionConfig {
+            jump_hosts: vec![first.id.clone()],
+            ..password_config(addr)
+        }).await.unwrap();
+        inventory.update(SSHConnectionConfig {
+            jump_hosts: vec![second.id.clone()],
+            ..first.clone()
+        }).await.unwrap();
+
+        let manager = SSHManager::new()
+            .with_credential_store(credential_store)
+            .with_inventory(inventory);
+        let error = manager.create_connection(SSHConnectionConfig {
+            jump_hosts: vec![first.id.clone()],
+            ..password_config(addr)
+        }).await.unwrap_err();
+
+        assert!(matches!(&error, SSHError::InvalidState(message) if message.contains(&first.id)), "{:?}", error);
+        assert!(manager.list_connections().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_missing_jump_host_is_not_found() {
+        let manager = SSHManager::new()
+            .with_credential_store(CredentialStore::in_memory())
+            .with_inventory(temp_inventory(CredentialStore::in_memory()));
+        let error = manager.create_connection(SSHConnectionConfig {
+            jump_hosts: vec!["missing".to_string()],
+            ..password_config("127.0.0.1:22".parse().unwrap())
+        }).await.unwrap_err();
+
+        assert_eq!(error, SSHError::not_found(ResourceKind::JumpHost, "missing"));
+    }
+
+    #[tokio::test]
+    async fn test_removing_a_connection_keeps_the_saved_host_secrets() {
+        let credential_store = CredentialStore::in_memory();
+        let inventory = temp_inventory(credential_store.clone());
+        let host = inventory.create(password_config("127.0.0.1:1".parse().unwrap())).await.unwrap();
+        let host_password = host.password_ref.clone().unwrap();
+
+        let manager = SSHManager::new()
+            .with_credential_store(credential_store.clone())
+            .with_inventory(inventory.clone());
+
+        // Opened as saved, and again with a password typed in for this session only
+        let saved_id = manager.create_connection(host.clone()).await.unwrap();
+        manager.remove_connection(&saved_id).await.unwrap();
+        let typed_id = manager.create_connection(SSHConnectionConfig {
+            password: Some("typed".to_string()),
+            ..host.clone()
+        }).await.unwrap();
+        let typed_password = manager.get_connection(&typed_id).await.unwrap().config.password_ref.clone().unwrap();
+        assert_ne!(typed_password, host_password);
+        assert_eq!(credential_store.load(&host_password), Ok(Some(TEST_PASSWORD.to_string())));
+
+        manager.remove_connection(&typed_id).await.unwrap();
+        assert_eq!(credential_store.load(&typed_password), Ok(None));
+        assert_eq!(credential_store.load(&host_password), Ok(Some(TEST_PASSWORD.to_string())));
+        assert_eq!(inventory.get(&host.id).await.unwrap().password_ref, Some(host_password));
+    }
+
+    #[tokio::test]
+    async fn test_create_get_and_list_terminal_sessions() {
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+        let size = TerminalSize {
+            cols: 100,
+            rows: 40,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+
+        let terminal_id = manager
+            .create_terminal_session(&connection_id, Some(size), Some("vt100".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            server.terminal_requests.lock().unwrap()[0],
+            TerminalRequest::Pty { term: "vt100".to_string(), cols: 100, rows: 40 }
+        );
+
+        let session = manager.get_terminal_session(&connection_id, &terminal_id).await.unwrap();
+        assert_eq!(session.connection_id, connection_id);
+        assert_eq!((session.size.cols, session.size.rows), (100, 40));
+        assert_eq!(session.term, "vt100");
+
+        let sessions = manager.list_terminal_sessions(&connection_id).await.unwrap();
+        assert_eq!(sessions.iter().map(|session| session.id.as_str()).collect::<Vec<_>>(), vec![terminal_id.as_str()]);
+
+        assert_eq!(
+            manager.get_terminal_session(&connection_id, "missing").await.unwrap_err(),
+            SSHError::not_found(ResourceKind::TerminalSession, "missing")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_terminal_input_reaches_the_shell() {
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+
+        manager.send_terminal_input(&connection_id, &terminal_id, b"echo\n").await.unwrap();
+        assert_eq!(next_output(&manager, &terminal_id).await, b"echo\n");
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_input_reaches_terminals_on_every_connection() {
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+        let other_id = manager.create_connection(password_config(server.addr)).await.unwrap();
+        wait_for_status(&manager, &other_id, ConnectionStatus::Connected).await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+        let other_terminal_id = manager.create_terminal_session(&other_id, None, None).await.unwrap();
+
+        let group = manager
+            .create_broadcast_group("fleet".to_string(), vec![terminal_id.clone(), other_terminal_id.clone()])
+            .await
+            .unwrap();
+        let result = manager.broadcast_terminal_input(&group.id, b"uptime\n").await.unwrap();
+        assert_eq!(result.failed, 0);
+        // The two shells answer in either order
+        let receiver = manager.get_event_receiver().await;
+        let mut outputs = HashMap::new();
+        timeout(Duration::from_secs(5), async {
+            while outputs.len() < 2 {
+                if let Some(SSHEvent::Data(_, id, _, _, data)) = receiver.lock().await.recv().await {
+                    outputs.insert(id, data);
+                }
+            }
+        })
+        .await
+        .expect("not every terminal got the input");
+        assert_eq!(outputs[&terminal_id], b"uptime\n");
+        assert_eq!(outputs[&other_terminal_id], b"uptime\n");
+
+        // Sharing a terminal manager doesn't let one connection reach another's terminals
+        assert_eq!(
+            manager.send_terminal_input(&connection_id, &other_terminal_id, b"x").await,
+            Err(SSHError::not_found(ResourceKind::TerminalSession, &other_terminal_id))
+        );
+        assert!(manager.get_terminal_session(&connection_id, &other_terminal_id).await.is_err());
+        assert_eq!(manager.list_terminal_sessions(&other_id).await.unwrap().len(), 1);
+
+        manager.remove_connection(&other_id).await.unwrap();
+        let group = manager.list_broadcast_groups().await.remove(0);
+        assert_eq!(group.terminal_ids, vec![terminal_id]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_terminal_session_output() {
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+
+        manager.send_terminal_input(&connection_id, &terminal_id, b"ls\n").await.unwrap();
+        next_output(&manager, &terminal_id).await;
+        manager.send_terminal_input(&connection_id, &terminal_id, b"pwd\n").await.unwrap();
+        next_output(&manager, &terminal_id).await;
+
+        let replay = manager.replay_terminal_session(&connection_id, &terminal_id).await.unwrap();
+        assert_eq!(replay.data, b"ls\npwd\n");
+        assert!(!replay.truncated);
+        assert_eq!(replay.offset, 7);
+
+        assert_eq!(
+            manager.replay_terminal_session(&connection_id, "missing").await,
+            Err(SSHError::not_found(ResourceKind::TerminalSession, "missing"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resize_terminal_session() {
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+        let size = TerminalSize {
+            cols: 132,
+            rows: 50,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+
+        manager.resize_terminal_session(&connection_id, &terminal_id, size).await.unwrap();
+
+        let session = manager.get_terminal_session(&connection_id, &terminal_id).await.unwrap();
+        assert_eq!((session.size.cols, session.size.rows), (132, 50));
+        // Window changes aren't answered, so the server sees this one eventually
+        timeout(Duration::from_secs(5), async {
+            while !server.terminal_requests.lock().unwrap().contains(&TerminalRequest::WindowChange { cols: 132, rows: 50 }) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the server never saw the window change");
+    }
+
+    #[tokio::test]
+    async fn test_close_terminal_session() {
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+
+        manager.close_terminal_session(&connection_id, &terminal_id).await.unwrap();
+
+        let not_found = SSHError::not_found(ResourceKind::TerminalSession, &terminal_id);
+        assert_eq!(manager.get_terminal_session(&connection_id, &terminal_id).await.unwrap_err(), not_found);
+        assert!(manager.list_terminal_sessions(&connection_id).await.unwrap().is_empty());
+        assert_eq!(manager.close_terminal_session(&connection_id, &terminal_id).await, Err(not_found));
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_and_reattaches_terminal_sessions() {
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+        let terminal_id = manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+        let size = TerminalSize { cols: 100, rows: 30, pixel_width: 800, pixel_height: 600 };
+        manager.resize_terminal_session(&connection_id, &terminal_id, size).await.unwrap();
+        let resized = TerminalRequest::WindowChange { cols: 100, rows: 30 };
+        timeout(Duration::from_secs(5), async {
+            while !server.terminal_requests.lock().unwrap().contains(&resized) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the server never saw the resize");
+        server.terminal_requests.lock().unwrap().clear();
+
+        server.disconnect_clients().await;
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Reconnecting { attempt: 1 }).await;
+        let detached = manager.get_terminal_session(&connection_id, &terminal_id).await.unwrap();
+        assert!(!detached.is_active);
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+
+        // The same terminal id gets a new PTY of its last size and a new shell
+        timeout(Duration::from_secs(5), async {
+            while manager.send_terminal_input(&connection_id, &terminal_id, b"back\n").await.is_err() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("terminal session was never reattached");
+        assert_eq!(next_output(&manager, &terminal_id).await, b"back\n");
+
+        let sessions = manager.list_terminal_sessions(&connection_id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, terminal_id);
+        assert!(sessions[0].is_active);
+        assert_eq!(
+            server.terminal_requests.lock().unwrap()[..2],
+            [
+                TerminalRequest::Pty { term: DEFAULT_TERM.to_string(), cols: 100, rows: 30 },
+                TerminalRequest::Shell,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lost_connection_without_reconnect_policy_fails() {
+        let server = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let manager = SSHManager::new()
+            .with_credential_store(CredentialStore::in_memory())
+            .with_known_hosts(server.known_hosts());
+        let mut config = password_config(server.addr);
+        config.reconnect.enabled = false;
+        let connection_id = manager.create_connection(config).await.unwrap();
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+        manager.create_terminal_session(&connection_id, None, None).await.unwrap();
+
+        server.disconnect_clients().await;
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Error).await;
+
+        let state = manager.get_connection_state(&connection_id).await.unwrap();
+        assert!(matches!(state.error, Some(SSHError::ConnectionLost { .. })));
+        assert!(manager.list_terminal_sessions(&connection_id).await.unwrap().is_empty());
+        assert!(!manager.is_connected(&connection_id).await);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_does_not_reconnect() {
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+
+        manager.disconnect_connection(&connection_id).await.unwrap();
+        sleep(Duration::from_millis(400)).await;
+
+        let state = manager.get_connection_state(&connection_id).await.unwrap();
+        assert_eq!(state.status, ConnectionStatus::Disconnected);
+        assert!(!manager.is_connected(&connection_id).await);
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_keepalives_fail_the_connection() {
+        let server = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let relay = TestRelay::start(server.addr).await;
+        let known_hosts = empty_known_hosts();
+        known_hosts.learn(&relay.addr.ip().to_string(), relay.addr.port(), &server.host_key).unwrap();
+        let manager = SSHManager::new()
+            .with_credential_store(CredentialStore::in_memory())
+            .with_known_hosts(known_hosts);
+
+        let mut config = password_config(relay.addr);
+        config.reconnect.enabled = false;
+        config.keepalive = KeepaliveConfig { interval_secs: 1, max_missed: 2 };
+        let connection_id = manager.create_connection(config).await.unwrap();
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+
+        // Still connected after several quiet intervals while the server answers probes
+        sleep(Duration::from_millis(2500)).await;
+        assert_eq!(
+            manager.get_connection_state(&connection_id).await.unwrap().status,
+            ConnectionStatus::Connected
+        );
+
+        relay.go_silent();
+        let receiver = manager.get_event_receiver().await;
+        let mut receiver = receiver.lock().await;
+        let error = timeout(Duration::from_secs(10), async {
+            loop {
+                match receiver.recv().await {
+                    Some(SSHEvent::Error(id, error)) if id == connection_id => return error,
+                    Some(_) => continue,
+                    None => panic!("event channel closed"),
+                }
+            }
+        })
+        .await
+        .expect("the dead connection was never noticed");
+
+        let expected = SSHError::KeepaliveTimeout { interval_secs: 1, missed: 2 };
+        assert_eq!(error, expected);
+        // Exactly `max_missed` probes went out before giving up
+        assert_eq!(relay.dropped_client_reads.load(std::sync::atomic::Ordering::SeqCst), 2);
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Error).await;
+        let state = manager.get_connection_state(&connection_id).await.unwrap();
+        assert_eq!(state.error, Some(expected));
+    }
+
+    /// Starts a TCP server that echoes whatever it receives
+    async fn start_echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn echo_forward(echo: SocketAddr) -> LocalForward {
+        LocalForward {
+            bind_address: DEFAULT_BIND_ADDRESS.to_string(),
+            bind_port: 0,
+            target_host: echo.ip().to_string(),
+            target_port: echo.port(),
+            auto_start: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_forward_carries_traffic() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let echo = start_echo_server().await;
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+
+        let forward = manager.create_local_forward(&connection_id, echo_forward(echo)).await.unwrap();
+        let mut client = tokio::net::TcpStream::connect(&forward.listen_address).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut reply = [0; 4];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(&reply, b"ping");
+        assert_eq!(server.tunnels.lock().unwrap().as_slice(), &[echo]);
+
+        // Counters move just after the data does
+        let expected = ForwardStats { bytes_sent: 4, bytes_received: 4, connections: 1, active_connections: 1 };
+        timeout(Duration::from_secs(5), async {
+            while manager.list_port_forwards(&connection_id).await.unwrap()[0].stats != expected {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("traffic was never counted");
+
+        manager.stop_port_forward(&connection_id, &forward.id).await.unwrap();
+        assert!(manager.list_port_forwards(&connection_id).await.unwrap().is_empty());
+        let mut buffer = [0; 1];
+        let closed = timeout(Duration::from_secs(5), client.read(&mut buffer)).await.unwrap();
+        assert!(matches!(closed, Ok(0) | Err(_)));
+    }
+
+    #[tokio::test]
+    async fn test_local_forward_needs_a_connection() {
+        let echo = start_echo_server().await;
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        manager.disconnect_connection(&connection_id).await.unwrap();
+
+        let result = manager.create_local_forward(&connection_id, echo_forward(echo)).await;
+        assert_eq!(result.unwrap_err(), SSHError::NotConnected);
+        assert_eq!(
+            manager.create_local_forward("missing", echo_forward(echo)).await.unwrap_err(),
+            SSHError::not_found(ResourceKind::Connection, "missing")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auto_start_forwards_survive_reconnect() {
+        let echo = start_echo_server().await;
+        let server = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let manager = SSHManager::new()
+            .with_credential_store(CredentialStore::in_memory())
+            .with_known_hosts(server.known_hosts());
+        let mut config = password_config(server.addr);
+        config.local_forwards = vec![
+            LocalForward { auto_start: true, ..echo_forward(echo) },
+            // Saved but started by hand
+            LocalForward { bind_port: 1, ..echo_forward(echo) },
+        ];
+        let connection_id = manager.create_connection(config).await.unwrap();
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+
+        let forwards = manager.list_port_forwards(&connection_id).await.unwrap();
+        assert_eq!(forwards.len(), 1);
+        assert!(matches!(&forwards[0].rule, ForwardRule::Local(rule) if rule.auto_start));
+
+        // Reconnecting keeps the running forward rather than starting a second one
+        server.disconnect_clients().await;
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Reconnecting { attempt: 1 }).await;
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+        let after = manager.list_port_forwards(&connection_id).await.unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].id, forwards[0].id);
+
+        manager.disconnect_connection(&connection_id).await.unwrap();
+        assert!(manager.list_port_forwards(&connection_id).await.unwrap().is_empty());
+    }
+
+    fn echo_remote_forward(echo: SocketAddr) -> RemoteForward {
+        RemoteForward {
+            bind_address: "127.0.0.1".to_string(),
+            bind_port: 0,
+            target_host: echo.ip().to_string(),
+            target_port: echo.port(),
+            auto_start: false,
+        }
+    }
+
+    /// Sends a ping through a forward's listening address, waits for the echo and returns
+    /// the still-open connection
+    async fn ping_through(address: &str) -> tokio::net::TcpStream {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut reply = [0; 4];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(&reply, b"ping");
+        client
+    }
+
+    /// Waits for nothing to be listening on an address any more
+    async fn wait_until_closed(address: &str) {
+        timeout(Duration::from_secs(5), async {
+            while tokio::net::TcpStream::connect(address).await.is_ok() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the address is still listening");
+    }
+
+    #[tokio::test]
+    async fn test_remote_forward_carries_traffic() {
+        let echo = start_echo_server().await;
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+
+        let forward = manager.create_remote_forward(&connection_id, echo_remote_forward(echo)).await.unwrap();
+        assert!(matches!(forward.rule, ForwardRule::Remote(_)));
+        let listen_address: SocketAddr = forward.listen_address.parse().unwrap();
+        assert_ne!(listen_address.port(), 0);
+        let _client = ping_through(&forward.listen_address).await;
+
+        let expected = ForwardStats { bytes_sent: 4, bytes_received: 4, connections: 1, active_connections: 1 };
+        timeout(Duration::from_secs(5), async {
+            while manager.list_port_forwards(&connection_id).await.unwrap()[0].stats != expected {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("traffic was never counted");
+
+        // Stopping the forward has the server stop listening
+        manager.stop_port_forward(&connection_id, &forward.id).await.unwrap();
+        assert!(manager.list_port_forwards(&connection_id).await.unwrap().is_empty());
+        wait_until_closed(&forward.listen_address).await;
+    }
+
+    #[tokio::test]
+    async fn test_remote_forward_refused_by_server() {
+        let echo = start_echo_server().await;
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let rule = RemoteForward { bind_port: port, ..echo_remote_forward(echo) };
+        let error = manager.create_remote_forward(&connection_id, rule).await.unwrap_err();
+        assert_eq!(error, SSHError::ForwardRefused { address: "127.0.0.1".to_string(), port });
+        assert!(manager.list_port_forwards(&connection_id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_remote_forward_restored_after_reconnect_and_cancelled_on_disconnect() {
+        let echo = start_echo_server().await;
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+        let forward = manager.create_remote_forward(&connection_id, echo_remote_forward(echo)).await.unwrap();
+
+        // The server forgets the listener with the session, and is asked for the same port again
+        server.disconnect_clients().await;
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Reconnecting { attempt: 1 }).await;
+        wait_for_status(&manager, &connection_id, ConnectionStatus::Connected).await;
+        let after = manager.list_port_forwards(&connection_id).await.unwrap();
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].listen_address, forward.listen_address);
+        ping_through(&forward.listen_address).await;
+
+        manager.disconnect_connection(&connection_id).await.unwrap();
+        assert!(manager.list_port_forwards(&connection_id).await.unwrap().is_empty());
+        wait_until_closed(&forward.listen_address).await;
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_forward_connects_where_the_client_asks() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let echo = start_echo_server().await;
+        let (server, manager, connection_id) = connect_to_shell_server().await;
+        let rule = DynamicForward { bind_address: DEFAULT_BIND_ADDRESS.to_string(), bind_port: 0, auto_start: false };
+        let forward = manager.create_dynamic_forward(&connection_id, rule).await.unwrap();
+        let port = echo.port().to_be_bytes();
+
+        // SOCKS5, naming the target by address
+        let mut client = tokio::net::TcpStream::connect(&forward.listen_address).await.unwrap();
+        client.write_all(&[5, 1, 0, 5, 1, 0, 1, 127, 0, 0, 1, port[0], port[1]]).await.unwrap();
+        let mut reply = [0; 12];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(reply[..4], [5, 0, 5, 0]);
+        client.write_all(b"ping").await.unwrap();
+        let mut echoed = [0; 4];
+        timeout(Duration::from_secs(5), client.read_exact(&mut echoed)).await.unwrap().unwrap();
+        assert_eq!(&echoed, b"ping");
+        assert_eq!(server.tunnels.lock().unwrap().as_slice(), &[echo]);
+
+        // SOCKS4a, leaving the name for the server to resolve
+        let mut client = tokio::net::TcpStream::connect(&forward.listen_address).await.unwrap();
+        let mut request = vec![4, 1, port[0], port[1], 0, 0, 0, 1, 0];
+        request.extend_from_slice(b"localhost\0");
+        client.write_all(&request).await.unwrap();
+        let mut reply = [0; 8];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(reply[..2], [0, 0x5a]);
+
+        // A target the server can't reach is reported to the client
+        let unused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port().to_be_bytes();
+        let mut client = tokio::net::TcpStream::connect(&forward.listen_address).await.unwrap();
+        client.write_all(&[5, 1, 0, 5, 1, 0, 1, 127, 0, 0, 1, unused[0], unused[1]]).await.unwrap();
+        let mut reply = [0; 12];
+        timeout(Duration::from_secs(5), client.read_exact(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(reply[..4], [5, 0, 5, 1]);
+
+        let listed = manager.list_port_forwards(&connection_id).await.unwrap();
+        assert!(matches!(listed[0].rule, ForwardRule::Dynamic(_)));
+        assert_eq!(listed[0].stats.connections, 3);
+    }
+
+    #[tokio::test]
+    async fn test_batch_runs_on_tagged_hosts_and_reports_each() {
+        let server = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let manager = SSHManager::new()
+            .with_credential_store(CredentialStore::in_memory())
+            .with_known_hosts(server.known_hosts());
+        for (name, tags) in [("web-2", vec!["web"]), ("web-1", vec!["web", "prod"]), ("db-1", vec!["db"])] {
+            let config = SSHConnectionConfig {
+                name: name.to_string(),
+                tags: tags.into_iter().map(String::from).collect(),
+                ..password_config(server.addr)
+            };
+            manager.create_connection(config).await.unwrap();
+        }
+        // Nothing listens here any more, so this host can't be reached
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let unreachable = SSHConnectionConfig {
+            name: "web-3".to_string(),
+            tags: vec!["WEB".to_string()],
+            ..password_config(closed)
+        };
+        manager.create_connection(unreachable).await.unwrap();
+
+        // The hosts may still be connecting; the batch waits for them
+        let request = BatchRequest {
+            batch_id: Some("batch-1".to_string()),
+            targets: BatchTargets::Tags(vec!["web".to_string()]),
+            exec: ExecRequest::new("uptime"),
+            max_concurrency: Some(2),
+        };
+        let result = manager.run_batch(request).await.unwrap();
+        assert_eq!(result.batch_id, "batch-1");
+        let names: Vec<&str> = result.hosts.iter().map(|host| host.name.as_deref().unwrap()).collect();
+        assert_eq!(names, vec!["web-1", "web-2", "web-3"]);
+        assert_eq!((result.succeeded, result.failed), (2, 1));
+        assert_eq!(result.hosts[0].result.as_ref().unwrap().stdout, "uptime\n");
+        assert!(matches!(result.hosts[2].error, Some(SSHError::Tcp { .. })), "{:?}", result.hosts[2]);
+
+        let receiver = manager.get_event_receiver().await;
+        let mut receiver = receiver.lock().await;
+        let mut progress = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if let SSHEvent::BatchHostFinished(batch_id, finished, total, _) = event {
+                assert_eq!(batch_id, "batch-1");
+                progress.push((finished, total));
+            }
+        }
+        assert_eq!(progress, vec![(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_runs_on_saved_hosts_with_the_tags() {
+        let server = TestServer::start(TestServerConfig {
+            password: Some(TEST_PASSWORD.to_string()),
+            ..Default::default()
+        })
+        .await;
+        let credential_store = CredentialStore::in_memory();
+        let inventory = temp_inventory(credential_store.clone());
+        let manager = SSHManager::new()
+            .with_credential_store(credential_store)
+            .with_known_hosts(server.known_hosts())
+            .with_inventory(inventory.clone());
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let mut saved = Vec::new();
+        for (name, tags, addr) in [("web-1", "web", server.addr), ("web-2", "web", closed), ("db-1", "db", server.addr)] {
+            let config = SSHConnectionConfig {
+                name: name.to_string(),
+                tags: vec![tags.to_string()],
+                ..password_config(addr)
+            };
+            saved.push(inventory.create(config).await.unwrap());
+        }
+        // A saved host that already has a connection is run on through it, once
+        let connected = SSHConnectionConfig {
+            name: "web-3".to_string(),
+            tags: vec!["web".to_string()],
+            ..password_config(server.addr)
+        };
+        let connected = inventory.create(connected).await.unwrap();
+        manager.create_connection(connected.clone()).await.unwrap();
+
+        let request = BatchRequest {
+            batch_id: None,
+            targets: BatchTargets::Tags(vec!["web".to_string()]),
+            exec: ExecRequest::new("uptime"),
+            max_concurrency: None,
+        };
+        let result = manager.run_batch(request).await.unwrap();
+        let names: Vec<&str> = result.hosts.iter().map(|host| host.name.as_deref().unwrap()).collect();
+        assert_eq!(names, vec!["web-1", "web-2", "web-3"]);
+        assert_eq!((result.succeeded, result.failed), (2, 1));
+        assert_eq!(result.hosts[0].connection_id, saved[0].id);
+        assert_eq!(result.hosts[0].result.as_ref().unwrap().stdout, "uptime\n");
+        assert!(matches!(result.hosts[1].error, Some(SSHError::Tcp { .. })), "{:?}", result.hosts[1]);
+
+        // The saved host that was down now has a connection of its own, left connected
+        assert!(manager.is_connected(&saved[0].id).await);
+        assert!(manager.get_connection(&saved[2].id).await.is_none());
+        assert_eq!(manager.list_connections().await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_batch_connects_hosts_that_are_down() {
+        let (_server, manager, connection_id) = connect_to_shell_server().await;
+        manager.disconnect_connection(&connection_id).await.unwrap();
+
+        let request = BatchRequest {
+            batch_id: None,
+            targets: BatchTargets::Connections(vec![connection_id.clone(), "missing".to_string(), connection_id.clone()]),
+            exec: ExecRequest::new("exit 2"),
+            max_concurrency: None,
+        };
+        let invalid = BatchRequest { max_concurrency: Some(0), ..request.clone() };
+        assert!(matches!(manager.run_batch(invalid).await, Err(SSHError::Validation(_))));
+
+        let result = manager.run_batch(request).await.unwrap();
+        assert_eq!(result.hosts.len(), 2);
+        assert_eq!(result.hosts[0].result.as_ref().unwrap().exit_status, Some(2));
+        assert!(manager.is_connected(&connection_id).await);
+        assert_eq!(result.hosts[1].name, None);
+        assert_eq!(result.hosts[1].error, Some(SSHError::not_found(ResourceKind::Connection, "missing")));
+        assert_eq!((result.succeeded, result.failed), (0, 2));
+    }
+}
@@ -2,8 +2,26 @@ pub mod manager;
 pub mod connection;
 pub mod types;
 pub mod terminal;
+pub mod known_hosts;
+pub mod credentials;
+pub mod resolver;
+pub mod config_import;
+pub mod inventory;
+pub mod events;
+pub mod error;
+pub mod forwarding;
+pub mod socks;
+pub mod exec;
+
+#[cfg(test)]
+pub(crate) mod test_server;
 
 pub use manager::SSHManager;
 pub use connection::SSHConnection;
 pub use terminal::TerminalSessionManager;
+pub use forwarding::PortForwardManager;
+pub use known_hosts::KnownHosts;
+pub use credentials::CredentialStore;
+pub use inventory::HostInventory;
+pub use error::{SSHError, SSHResult};
 pub use types::*;
\ No newline at end of file
@@ -0,0 +1,253 @@
+use crate::ssh::error::{SSHError, SSHResult};
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, Duration};
+
+/// How long to wait on one address before also trying the next (RFC 8305 "Connection Attempt Delay")
+pub const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Strips the brackets from an IPv6 literal such as `[::1]`; other hosts are returned unchanged
+pub fn unbracket(hostname: &str) -> &str {
+    hostname
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(hostname)
+}
+
+/// Resolves a hostname or IP literal to every address it has, ordered for happy eyeballs
+pub async fn resolve(hostname: &str, port: u16) -> SSHResult<Vec<SocketAddr>> {
+    let host = unbracket(hostname);
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let dns_error = |reason: String| SSHError::Dns {
+        host: host.to_string(),
+        reason,
+    };
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| dns_error(e.to_string()))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(dns_error("no addresses found".to_string()));
+    }
+
+    Ok(interleave(addrs))
+}
+
+/// Orders addresses IPv6 first, alternating families so a broken family is skipped quickly
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (mut v6, mut v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    v6.dedup();
+    v4.dedup();
+
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (first, second) => ordered.extend(first.into_iter().chain(second)),
+        }
+    }
+    ordered
+}
+
+/// Connects to the first address that answers, starting a new attempt every
+/// `attempt_delay` or as soon as the previous one fails, and giving up after `connect_timeout`.
+/// `host` is the name the addresses were resolved from, for errors.
+pub async fn connect_any(
+    host: &str,
+    addrs: Vec<SocketAddr>,
+    attempt_delay: Duration,
+    connect_timeout: Duration,
+) -> SSHResult<TcpStream> {
+    let port = addrs.first().map(SocketAddr::port).unwrap_or_default();
+    let mut remaining = addrs.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut pending = Vec::new();
+    let mut errors = Vec::new();
+
+    let race = async {
+        loop {
+            if let Some(addr) = remaining.next() {
+                pending.push(addr);
+                attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
+            }
+            if attempts.is_empty() {
+                return None;
+            }
+
+            let more_addresses = remaining.len() > 0;
+            tokio::select! {
+                Some(joined) = attempts.join_next() => match joined {
+                    Ok((_, Ok(stream))) => return Some(stream),
+                    Ok((addr, Err(e))) => {
+                        pending.retain(|pending| *pending != addr);
+                        errors.push(format!("{}: {}", addr, e));
+                    }
+                    Err(e) => errors.push(e.to_string()),
+                },
+                _ = sleep(attempt_delay), if more_addresses => {}
+            }
+        }
+    };
+
+    match timeout(connect_timeout, race).await {
+        Ok(Some(stream)) => Ok(stream),
+        Ok(None) => Err(SSHError::Tcp {
+            host: host.to_string(),
+            port,
+            reason: errors.join(", "),
+        }),
+        Err(_) => {
+            errors.extend(pending.iter().map(|addr| format!("{}: no answer", addr)));
+            Err(SSHError::ConnectTimeout {
+                host: host.to_string(),
+                port,
+                timeout_secs: connect_timeout.as_secs_f32(),
+                errors,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_unbracket() {
+        assert_eq!(unbracket("[::1]"), "::1");
+        assert_eq!(unbracket("[fe80::1%eth0]"), "fe80::1%eth0");
+        assert_eq!(unbracket("example.com"), "example.com");
+        assert_eq!(unbracket("[unterminated"), "[unterminated");
+    }
+
+    #[test]
+    fn test_interleave_prefers_ipv6() {
+        let ordered = interleave(vec![
+            addr("192.0.2.1:22"),
+            addr("192.0.2.2:22"),
+            addr("192.0.2.3:22"),
+            addr("[2001:db8::1]:22"),
+            addr("[2001:db8::2]:22"),
+        ]);
+
+        assert_eq!(
+            ordered,
+            vec![
+                addr("[2001:db8::1]:22"),
+                addr("192.0.2.1:22"),
+                addr("[2001:db8::2]:22"),
+                addr("192.0.2.2:22"),
+                addr("192.0.2.3:22"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_literals() {
+        assert_eq!(resolve("127.0.0.1", 22).await, Ok(vec![addr("127.0.0.1:22")]));
+        assert_eq!(resolve("[::1]", 2222).await, Ok(vec![addr("[::1]:2222")]));
+        assert_eq!(resolve("::1", 22).await, Ok(vec![addr("[::1]:22")]));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_localhost() {
+        let addrs = resolve("localhost", 22).await.unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 22));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_unknown_host() {
+        let result = resolve("no-such-host.invalid", 22).await;
+        assert!(matches!(result, Err(SSHError::Dns { host, .. }) if host == "no-such-host.invalid"));
+    }
+
+    /// Returns an address that refuses connections
+    async fn closed_port() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connect_any_falls_back_after_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+        let refused = closed_port().await;
+
+        // A refused address moves on immediately instead of waiting out the attempt delay
+        let stream = connect_any("localhost", vec![refused, open], Duration::from_secs(30), Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+    }
+
+    #[tokio::test]
+    async fn test_connect_any_races_slow_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+
+        // 192.0.2.0/24 is reserved for documentation and never answers (or fails outright)
+        let stream = connect_any(
+            "localhost",
+            vec![addr("192.0.2.1:22"), open],
+            Duration::from_millis(50),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+    }
+
+    #[tokio::test]
+    async fn test_connect_any_reports_every_failure() {
+        let first = closed_port().await;
+        let second = closed_port().await;
+
+        let error = connect_any("localhost", vec![first, second], ATTEMPT_DELAY, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        let SSHError::Tcp { host, reason, .. } = error else {
+            panic!("Expected a TCP error, got {:?}", error);
+        };
+        assert_eq!(host, "localhost");
+        assert!(reason.contains(&first.to_string()));
+        assert!(reason.contains(&second.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_connect_any_timeout_keeps_each_address_error() {
+        let refused = closed_port().await;
+
+        let error = connect_any(
+            "localhost",
+            vec![refused, addr("192.0.2.1:22")],
+            Duration::from_millis(50),
+            Duration::from_millis(500),
+        )
+        .await
+        .unwrap_err();
+        // Some networks reject the documentation address outright instead of dropping it
+        match error {
+            SSHError::ConnectTimeout { host, errors, .. } => {
+                assert_eq!(host, "localhost");
+                assert!(errors[0].starts_with(&refused.to_string()), "{:?}", errors);
+                assert_eq!(errors[1], "192.0.2.1:22: no answer");
+            }
+            SSHError::Tcp { reason, .. } => assert!(reason.contains(&refused.to_string())),
+            error => panic!("Expected a timeout, got {:?}", error),
+        }
+    }
+}
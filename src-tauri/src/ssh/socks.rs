@@ -0,0 +1,264 @@
+//! The server side of the SOCKS handshake dynamic port forwards speak: SOCKS5 without
+//! authentication, plus SOCKS4 and 4a. Only CONNECT is supported, as with OpenSSH.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS4: u8 = 4;
+const SOCKS5: u8 = 5;
+const COMMAND_CONNECT: u8 = 1;
+
+const METHOD_NO_AUTHENTICATION: u8 = 0x00;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+const SOCKS4_GRANTED: u8 = 0x5a;
+const SOCKS4_REJECTED: u8 = 0x5b;
+
+/// Longest user id or host name accepted in a SOCKS4 request
+const MAX_SOCKS4_FIELD: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SocksVersion {
+    V4,
+    V5,
+}
+
+/// Where a client asked to be connected
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocksRequest {
+    pub version: SocksVersion,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Reads a client's greeting and CONNECT request. Requests that can't be served are
+/// answered with the matching failure before the error is returned.
+pub async fn accept<S>(stream: &mut S) -> io::Result<SocksRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match stream.read_u8().await? {
+        SOCKS5 => accept_socks5(stream).await,
+        SOCKS4 => accept_socks4(stream).await,
+        version => Err(invalid(format!("unsupported SOCKS version {}", version))),
+    }
+}
+
+/// Tells the client whether its connection was made; after a success the stream carries
+/// the connection's data
+pub async fn reply<S>(stream: &mut S, version: SocksVersion, granted: bool) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    match version {
+        SocksVersion::V5 => {
+            let code = if granted { REPLY_SUCCEEDED } else { REPLY_GENERAL_FAILURE };
+            socks5_reply(stream, code).await
+        }
+        SocksVersion::V4 => {
+            let code = if granted { SOCKS4_GRANTED } else { SOCKS4_REJECTED };
+            stream.write_all(&[0, code, 0, 0, 0, 0, 0, 0]).await
+        }
+    }
+}
+
+async fn accept_socks5<S>(stream: &mut S) -> io::Result<SocksRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let method_count = stream.read_u8().await?;
+    let mut methods = vec![0; method_count as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&METHOD_NO_AUTHENTICATION) {
+        stream.write_all(&[SOCKS5, METHOD_NONE_ACCEPTABLE]).await?;
+        return Err(invalid("the client requires authentication".to_string()));
+    }
+    stream.write_all(&[SOCKS5, METHOD_NO_AUTHENTICATION]).await?;
+
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    let [version, command, _, address_type] = header;
+    if version != SOCKS5 {
+        return Err(invalid(format!("unsupported SOCKS version {}", version)));
+    }
+    if command != COMMAND_CONNECT {
+        socks5_reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid(format!("unsupported SOCKS command {}", command)));
+    }
+
+    let host = match address_type {
+        ADDRESS_IPV4 => {
+            let mut address = [0; 4];
+            stream.read_exact(&mut address).await?;
+            Ipv4Addr::from(address).to_string()
+        }
+        ADDRESS_IPV6 => {
+            let mut address = [0; 16];
+            stream.read_exact(&mut address).await?;
+            Ipv6Addr::from(address).to_string()
+        }
+        ADDRESS_DOMAIN => {
+            let length = stream.read_u8().await?;
+            let mut domain = vec![0; length as usize];
+            stream.read_exact(&mut domain).await?;
+            match String::from_utf8(domain) {
+                Ok(domain) => domain,
+                Err(_) => {
+                    socks5_reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+                    return Err(invalid("the host name is not valid UTF-8".to_string()));
+                }
+            }
+        }
+        _ => {
+            socks5_reply(stream, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(invalid(format!("unsupported SOCKS address type {}", address_type)));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    Ok(SocksRequest { version: SocksVersion::V5, host, port })
+}
+
+async fn accept_socks4<S>(stream: &mut S) -> io::Result<SocksRequest>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let command = stream.read_u8().await?;
+    let port = stream.read_u16().await?;
+    let mut address = [0; 4];
+    stream.read_exact(&mut address).await?;
+    // The user id is ignored; there is no authentication
+    read_null_terminated(stream).await?;
+
+    if command != COMMAND_CONNECT {
+        reply(stream, SocksVersion::V4, false).await?;
+        return Err(invalid(format!("unsupported SOCKS command {}", command)));
+    }
+
+    // SOCKS4a: an address of 0.0.0.x means the host name follows the user id
+    let host = if address[..3] == [0, 0, 0] && address[3] != 0 {
+        String::from_utf8(read_null_terminated(stream).await?)
+            .map_err(|_| invalid("the host name is not valid UTF-8".to_string()))?
+    } else {
+        Ipv4Addr::from(address).to_string()
+    };
+
+    Ok(SocksRequest { version: SocksVersion::V4, host, port })
+}
+
+async fn socks5_reply<S>(stream: &mut S, code: u8) -> io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    // The bound address is of no use through a tunnel, so it is left unspecified
+    stream.write_all(&[SOCKS5, code, 0, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0]).await
+}
+
+async fn read_null_terminated<S>(stream: &mut S) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut field = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => return Ok(field),
+            _ if field.len() == MAX_SOCKS4_FIELD => {
+                return Err(invalid("a SOCKS4 field is too long".to_string()));
+            }
+            byte => field.push(byte),
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `accept` against a client that sends `request`, returning the result and
+    /// everything written back to the client
+    async fn accept_bytes(request: &[u8]) -> (io::Result<SocksRequest>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(request).await.unwrap();
+        let result = accept(&mut server).await;
+        drop(server);
+        let mut written = Vec::new();
+        client.read_to_end(&mut written).await.unwrap();
+        (result, written)
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_requests() {
+        let (request, written) = accept_bytes(&[5, 1, 0, 5, 1, 0, 3, 7, b'g', b'r', b'a', b'f', b'a', b'n', b'a', 0, 80]).await;
+        assert_eq!(
+            request.unwrap(),
+            SocksRequest { version: SocksVersion::V5, host: "grafana".to_string(), port: 80 }
+        );
+        assert_eq!(written, [5, 0]);
+
+        let (request, _) = accept_bytes(&[5, 2, 2, 0, 5, 1, 0, 1, 10, 0, 0, 7, 0x1f, 0x90]).await;
+        assert_eq!(request.unwrap().host, "10.0.0.7");
+
+        let mut ipv6 = vec![5, 1, 0, 5, 1, 0, 4];
+        ipv6.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ipv6.extend_from_slice(&[0, 22]);
+        let (request, _) = accept_bytes(&ipv6).await;
+        let request = request.unwrap();
+        assert_eq!((request.host.as_str(), request.port), ("::1", 22));
+    }
+
+    #[tokio::test]
+    async fn test_socks5_unsupported_requests_are_answered() {
+        // Only username/password authentication offered
+        let (request, written) = accept_bytes(&[5, 1, 2]).await;
+        assert!(request.is_err());
+        assert_eq!(written, [5, METHOD_NONE_ACCEPTABLE]);
+
+        // BIND
+        let (request, written) = accept_bytes(&[5, 1, 0, 5, 2, 0, 1, 10, 0, 0, 7, 0, 80]).await;
+        assert!(request.is_err());
+        assert_eq!(written[2..4], [5, REPLY_COMMAND_NOT_SUPPORTED]);
+
+        let (request, written) = accept_bytes(&[5, 1, 0, 5, 1, 0, 9]).await;
+        assert!(request.is_err());
+        assert_eq!(written[2..4], [5, REPLY_ADDRESS_NOT_SUPPORTED]);
+    }
+
+    #[tokio::test]
+    async fn test_socks4_and_4a_connect_requests() {
+        let (request, written) = accept_bytes(&[4, 1, 0, 80, 192, 168, 1, 2, b'm', b'e', 0]).await;
+        assert_eq!(
+            request.unwrap(),
+            SocksRequest { version: SocksVersion::V4, host: "192.168.1.2".to_string(), port: 80 }
+        );
+        // Nothing is written until the connection is made
+        assert!(written.is_empty());
+
+        let (request, _) = accept_bytes(&[4, 1, 0, 80, 0, 0, 0, 1, 0, b'w', b'i', b'k', b'i', 0]).await;
+        assert_eq!(request.unwrap().host, "wiki");
+
+        let (request, written) = accept_bytes(&[4, 2, 0, 80, 192, 168, 1, 2, 0]).await;
+        assert!(request.is_err());
+        assert_eq!(written, [0, SOCKS4_REJECTED, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_version_is_rejected() {
+        let (request, written) = accept_bytes(b"GET / HTTP/1.1\r\n").await;
+        assert_eq!(request.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(written.is_empty());
+    }
+}
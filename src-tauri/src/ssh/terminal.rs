@@ -1,45 +1,156 @@
+use crate::ssh::error::{ResourceKind, SSHError, SSHResult};
 use crate::ssh::types::*;
 use russh::client::Msg;
-use russh::Channel;
-use std::collections::HashMap;
+use russh::{Channel, ChannelMsg};
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use tokio::task::JoinHandle;
+use tokio::sync::{mpsc, oneshot, watch, Mutex, RwLock};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{timeout, Duration};
+
+/// How long the server has to answer a PTY or shell request
+const CHANNEL_REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Requests that may queue up for a running session's channel
+const CHANNEL_REQUEST_QUEUE: usize = 64;
+
+/// How long one member of a broadcast group has to take its input before it's reported
+/// as failed
+const BROADCAST_DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Becomes `Some(why)` when the SSH session a channel belongs to ends. It is set before
+/// the session's channels close, so a closed channel can be told apart from a lost
+/// connection.
+pub type SessionEnded = watch::Receiver<Option<SSHError>>;
 
 pub struct TerminalSessionManager {
     sessions: Arc<RwLock<HashMap<String, TerminalSessionData>>>,
+    /// Broadcast groups by id; closed terminals leave them
+    groups: Arc<RwLock<HashMap<String, BroadcastGroup>>>,
     event_sender: mpsc::Sender<SSHEvent>,
 }
 
 struct TerminalSessionData {
     session: TerminalSession,
+    /// The session channel until its shell starts; the I/O task owns it afterwards
     ssh_channel: Option<Channel<Msg>>,
-    input_task: Option<JoinHandle<()>>,
-    output_task: Option<JoinHandle<()>>,
+    /// Feeds the I/O task once the shell is running
+    channel_requests: Option<mpsc::Sender<ChannelRequest>>,
+    io_task: Option<JoinHandle<()>>,
+    /// Ending of the SSH session the channel belongs to
+    session_ended: SessionEnded,
+    /// Recent output, kept across reattaching. It has its own lock so recording output
+    /// doesn't wait on, or hold up, other sessions.
+    scrollback: Arc<Mutex<Scrollback>>,
+}
+
+/// A terminal's most recent raw output, trimmed from the front to the configured limits
+struct Scrollback {
+    data: VecDeque<u8>,
+    /// Newlines in `data`
+    lines: usize,
+    limits: ScrollbackConfig,
+    /// Whether older output has been dropped
+    truncated: bool,
+    /// Bytes pushed in all, which is where the next output starts
+    offset: u64,
+}
+
+impl Scrollback {
+    fn new(limits: ScrollbackConfig) -> Self {
+        Self {
+            data: VecDeque::new(),
+            lines: 0,
+            limits,
+            truncated: false,
+            offset: 0,
+        }
+    }
+
+    /// Adds `output` and returns where it starts among all the output pushed so far
+    fn push(&mut self, output: &[u8]) -> u64 {
+        let start = self.offset;
+        self.offset += output.len() as u64;
+
+        // Output bigger than the whole buffer only leaves its tail
+        let kept = &output[output.len().saturating_sub(self.limits.max_bytes)..];
+        self.truncated |= kept.len() < output.len();
+        self.lines += kept.iter().filter(|&&byte| byte == b'\n').count();
+        self.data.extend(kept);
+
+        if self.data.len() > self.limits.max_bytes {
+            self.drop_front(self.data.len() - self.limits.max_bytes);
+        }
+        while self.lines > self.limits.max_lines {
+            let Some(end) = self.data.iter().position(|&byte| byte == b'\n') else {
+                break;
+            };
+            self.drop_front(end + 1);
+        }
+        // Don't start a replay in the middle of a UTF-8 character
+        let partial = self.data.iter().take_while(|&&byte| byte & 0xc0 == 0x80).count();
+        if partial > 0 {
+            self.drop_front(partial);
+        }
+        start
+    }
+
+    fn drop_front(&mut self, count: usize) {
+        self.lines -= self.data.drain(..count).filter(|&byte| byte == b'\n').count();
+        self.truncated = true;
+    }
+}
+
+/// Work handed to the task that owns a running session's channel
+enum ChannelRequest {
+    Data(Vec<u8>, oneshot::Sender<SSHResult<()>>),
+    Resize(TerminalSize, oneshot::Sender<SSHResult<()>>),
+    Close,
 }
 
 impl TerminalSessionManager {
     pub fn new(event_sender: mpsc::Sender<SSHEvent>) -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            groups: Arc::new(RwLock::new(HashMap::new())),
             event_sender,
         }
     }
 
-    /// Creates a new terminal session for the given SSH connection
-    pub async fn create_session(
+    /// Starts a session opened with `open_session`: requests a PTY of the given size and
+    /// TERM type, then the user's shell. The session is closed if either request fails.
+    pub async fn start_session(&self, terminal_id: &str, size: TerminalSize, term: String) -> SSHResult<()> {
+        let started = async {
+            self.request_pty(terminal_id, size, term).await?;
+            self.request_shell(terminal_id).await
+        };
+        if let Err(e) = started.await {
+            let _ = self.close_session(terminal_id).await;
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Registers a session channel that isn't running anything yet and returns its terminal
+    /// id. Its output is kept within the `scrollback` limits.
+    pub async fn open_session(
         &self,
         connection_id: String,
-    ) -> Result<String, String> {
+        channel: Channel<Msg>,
+        session_ended: SessionEnded,
+        scrollback: ScrollbackConfig,
+    ) -> String {
         let terminal_session = TerminalSession::new(connection_id.clone());
         let terminal_id = terminal_session.id.clone();
 
-        // Create terminal session data (simplified for now)
         let session_data = TerminalSessionData {
             session: terminal_session,
-            ssh_channel: None, // Will be set up later when we have proper SSH channel management
-            input_task: None,
-            output_task: None,
+            ssh_channel: Some(channel),
+            channel_requests: None,
+            io_task: None,
+            session_ended,
+            scrollback: Arc::new(Mutex::new(Scrollback::new(scrollback))),
         };
 
         // Store session
@@ -48,93 +159,260 @@ impl TerminalSessionManager {
             sessions.insert(terminal_id.clone(), session_data);
         }
 
-        // Start I/O handling tasks
-        self.start_io_tasks(&terminal_id, &connection_id).await?;
-
         // Send terminal created event
         let _ = self
             .event_sender
             .send(SSHEvent::TerminalCreated(connection_id, terminal_id.clone()))
             .await;
 
-        Ok(terminal_id)
-    }
-
-    /// Starts the input/output handling tasks for a terminal session
-    async fn start_io_tasks(&self, terminal_id: &str, _connection_id: &str) -> Result<(), String> {
-        let sessions = self.sessions.clone();
-        let _event_sender = self.event_sender.clone();
-        let terminal_id = terminal_id.to_string();
-
-        // Start output task - this will be handled by the SSH client handler
-        // For now, we just create a placeholder task
-        let output_task = {
-            let sessions = sessions.clone();
-            let terminal_id = terminal_id.clone();
-
-            tokio::spawn(async move {
-                // This task monitors the session and handles cleanup
-                loop {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
-                    
-                    // Check if session still exists
-                    let sessions = sessions.read().await;
-                    if !sessions.contains_key(&terminal_id) {
-                        break;
-                    }
-                }
-            })
+        terminal_id
+    }
+
+    /// Requests a pseudo-terminal for a session whose shell hasn't started yet
+    pub async fn request_pty(&self, terminal_id: &str, size: TerminalSize, term: String) -> SSHResult<()> {
+        let mut channel = self.take_channel(terminal_id, "A PTY").await?;
+
+        let result = async {
+            channel
+                .request_pty(
+                    true,
+                    &term,
+                    size.cols as u32,
+                    size.rows as u32,
+                    size.pixel_width as u32,
+                    size.pixel_height as u32,
+                    &[],
+                )
+                .await
+                .map_err(|e| SSHError::channel("Failed to request a PTY", e))?;
+            wait_for_reply(&mut channel, "PTY").await
+        }
+        .await;
+
+        self.restore_channel(terminal_id, channel).await;
+        result?;
+
+        let mut sessions = self.sessions.write().await;
+        if let Some(session_data) = sessions.get_mut(terminal_id) {
+            session_data.session.size = size;
+            session_data.session.term = term;
+        }
+        Ok(())
+    }
+
+    /// Starts the user's login shell on a session and hands its channel to an I/O task
+    pub async fn request_shell(&self, terminal_id: &str) -> SSHResult<()> {
+        let mut channel = self.take_channel(terminal_id, "A shell").await?;
+
+        let result = async {
+            channel
+                .request_shell(true)
+                .await
+                .map_err(|e| SSHError::channel("Failed to request a shell", e))?;
+            wait_for_reply(&mut channel, "shell").await
+        }
+        .await;
+        if let Err(e) = result {
+            self.restore_channel(terminal_id, channel).await;
+            return Err(e);
+        }
+
+        let mut sessions = self.sessions.write().await;
+        let Some(session_data) = sessions.get_mut(terminal_id) else {
+            // Closed while the shell was starting
+            let _ = channel.close().await;
+            return Err(SSHError::not_found(ResourceKind::TerminalSession, terminal_id));
         };
 
-        // Start input task - handles input from frontend to SSH
-        let input_task = {
-            let sessions = sessions.clone();
-            let terminal_id = terminal_id.clone();
-
-            tokio::spawn(async move {
-                // This task will be driven by external input via send_input method
-                // For now, it's a placeholder that keeps the task alive
-                loop {
-                    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-                    
-                    // Check if session still exists
-                    let sessions = sessions.read().await;
-                    if !sessions.contains_key(&terminal_id) {
-                        break;
-                    }
-                }
+        let (request_sender, request_receiver) = mpsc::channel(CHANNEL_REQUEST_QUEUE);
+        session_data.channel_requests = Some(request_sender);
+        session_data.io_task = Some(tokio::spawn(run_channel(
+            channel,
+            request_receiver,
+            self.sessions.clone(),
+            self.groups.clone(),
+            self.event_sender.clone(),
+            session_data.session_ended.clone(),
+            terminal_id.to_string(),
+        )));
+
+        Ok(())
+    }
+
+    /// Takes a session's channel out so a request can wait for its reply without holding the lock
+    async fn take_channel(&self, terminal_id: &str, what: &str) -> SSHResult<Channel<Msg>> {
+        let mut sessions = self.sessions.write().await;
+        let session_data = sessions
+            .get_mut(terminal_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::TerminalSession, terminal_id))?;
+
+        session_data.ssh_channel.take().ok_or_else(|| {
+            SSHError::InvalidState(if session_data.channel_requests.is_some() {
+                format!("{} can't be requested once the shell is running", what)
+            } else {
+                format!("Another request is pending for terminal session {}", terminal_id)
             })
-        };
+        })
+    }
 
-        // Store task handles
+    /// Puts a channel back after a request; closes it if the session went away meanwhile
+    async fn restore_channel(&self, terminal_id: &str, channel: Channel<Msg>) {
+        let mut sessions = self.sessions.write().await;
+        match sessions.get_mut(terminal_id) {
+            Some(session_data) => session_data.ssh_channel = Some(channel),
+            None => {
+                let _ = channel.close().await;
+            }
+        }
+    }
+
+    /// Sends input to a terminal session
+    pub async fn send_input(&self, terminal_id: &str, data: &[u8]) -> SSHResult<()> {
+        send_data(self.channel_requests(terminal_id).await?, data.to_vec()).await
+    }
+
+    /// Hands a request to the session's I/O task
+    async fn send_request(&self, terminal_id: &str, request: ChannelRequest) -> SSHResult<()> {
+        self.channel_requests(terminal_id)
+            .await?
+            .send(request)
+            .await
+            .map_err(|_| channel_closed())
+    }
+
+    /// The queue feeding a running session's I/O task
+    async fn channel_requests(&self, terminal_id: &str) -> SSHResult<mpsc::Sender<ChannelRequest>> {
+        let sessions = self.sessions.read().await;
+        let session_data = sessions
+            .get(terminal_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::TerminalSession, terminal_id))?;
+        session_data
+            .channel_requests
+            .clone()
+            .ok_or_else(|| SSHError::Channel {
+                reason: "SSH channel not available".to_string(),
+            })
+    }
+
+    /// Creates a broadcast group of the given terminals, which must all exist
+    pub async fn create_broadcast_group(&self, name: String, terminal_ids: Vec<String>) -> SSHResult<BroadcastGroup> {
+        let mut group = BroadcastGroup::new(name);
         {
-            let mut sessions = self.sessions.write().await;
-            if let Some(session_data) = sessions.get_mut(&terminal_id) {
-                session_data.output_task = Some(output_task);
-                session_data.input_task = Some(input_task);
+            let sessions = self.sessions.read().await;
+            for terminal_id in terminal_ids {
+                if !sessions.contains_key(&terminal_id) {
+                    return Err(SSHError::not_found(ResourceKind::TerminalSession, &terminal_id));
+                }
+                if !group.terminal_ids.contains(&terminal_id) {
+                    group.terminal_ids.push(terminal_id);
+                }
             }
         }
 
-        Ok(())
+        self.groups.write().await.insert(group.id.clone(), group.clone());
+        Ok(group)
     }
 
-    /// Sends input to a terminal session
-    pub async fn send_input(&self, terminal_id: &str, data: &[u8]) -> Result<(), String> {
+    /// Adds a terminal to a broadcast group; joining twice changes nothing
+    pub async fn join_broadcast_group(&self, group_id: &str, terminal_id: &str) -> SSHResult<BroadcastGroup> {
+        // Lock order: sessions, then groups, as when a session closes
         let sessions = self.sessions.read().await;
-        if let Some(session_data) = sessions.get(terminal_id) {
-            // Send data to SSH channel
-            if let Some(ref channel) = session_data.ssh_channel {
-                channel
-                    .data(data)
-                    .await
-                    .map_err(|e| format!("Failed to send data to SSH channel: {}", e))?;
-                Ok(())
-            } else {
-                Err("SSH channel not available".to_string())
+        if !sessions.contains_key(terminal_id) {
+            return Err(SSHError::not_found(ResourceKind::TerminalSession, terminal_id));
+        }
+
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(group_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::BroadcastGroup, group_id))?;
+        if !group.terminal_ids.iter().any(|id| id == terminal_id) {
+            group.terminal_ids.push(terminal_id.to_string());
+        }
+        Ok(group.clone())
+    }
+
+    /// Removes a terminal from a broadcast group. The group stays, even when empty, until
+    /// it is deleted.
+    pub async fn leave_broadcast_group(&self, group_id: &str, terminal_id: &str) -> SSHResult<BroadcastGroup> {
+        let mut groups = self.groups.write().await;
+        let group = groups
+            .get_mut(group_id)
+            .ok_or_else(|| SSHError::not_found(ResourceKind::BroadcastGroup, group_id))?;
+        let before = group.terminal_ids.len();
+        group.terminal_ids.retain(|id| id != terminal_id);
+        if group.terminal_ids.len() == before {
+            return Err(SSHError::InvalidState(format!(
+                "Terminal session {} is not in broadcast group {}",
+                terminal_id, group_id
+            )));
+        }
+        Ok(group.clone())
+    }
+
+    /// Deletes a broadcast group; its terminals are left running
+    pub async fn delete_broadcast_group(&self, group_id: &str) -> SSHResult<()> {
+        self.groups
+            .write()
+            .await
+            .remove(group_id)
+            .map(|_| ())
+            .ok_or_else(|| SSHError::not_found(ResourceKind::BroadcastGroup, group_id))
+    }
+
+    pub async fn get_broadcast_group(&self, group_id: &str) -> Option<BroadcastGroup> {
+        self.groups.read().await.get(group_id).cloned()
+    }
+
+    /// Lists broadcast groups, oldest first
+    pub async fn list_broadcast_groups(&self) -> Vec<BroadcastGroup> {
+        let mut groups: Vec<BroadcastGroup> = self.groups.read().await.values().cloned().collect();
+        groups.sort_by_key(|group| group.created_at);
+        groups
+    }
+
+    /// Sends the same input to every terminal in a broadcast group at once. A member that
+    /// can't take it, or takes longer than `BROADCAST_DELIVERY_TIMEOUT`, is reported in its
+    /// row without holding up the others.
+    pub async fn broadcast_input(&self, group_id: &str, data: &[u8]) -> SSHResult<BroadcastResult> {
+        let terminal_ids = self
+            .get_broadcast_group(group_id)
+            .await
+            .ok_or_else(|| SSHError::not_found(ResourceKind::BroadcastGroup, group_id))?
+            .terminal_ids;
+
+        let mut deliveries = JoinSet::new();
+        for (index, terminal_id) in terminal_ids.iter().enumerate() {
+            let channel_requests = self.channel_requests(terminal_id).await;
+            let data = data.to_vec();
+            let operation = format!("sending input to terminal session {}", terminal_id);
+            deliveries.spawn(async move {
+                let result = match channel_requests {
+                    Ok(channel_requests) => timeout(BROADCAST_DELIVERY_TIMEOUT, send_data(channel_requests, data))
+                        .await
+                        .unwrap_or(Err(SSHError::Timeout { operation })),
+                    Err(e) => Err(e),
+                };
+                (index, result.err())
+            });
+        }
+
+        let mut errors = vec![None; terminal_ids.len()];
+        while let Some(delivery) = deliveries.join_next().await {
+            if let Ok((index, error)) = delivery {
+                errors[index] = error;
             }
-        } else {
-            Err(format!("Terminal session {} not found", terminal_id))
         }
+
+        let deliveries: Vec<BroadcastDelivery> = terminal_ids
+            .into_iter()
+            .zip(errors)
+            .map(|(terminal_id, error)| BroadcastDelivery { terminal_id, error })
+            .collect();
+        Ok(BroadcastResult {
+            group_id: group_id.to_string(),
+            failed: deliveries.iter().filter(|delivery| delivery.error.is_some()).count(),
+            deliveries,
+        })
     }
 
     /// Resizes a terminal session
@@ -145,55 +423,69 @@ impl TerminalSessionManager {
         rows: u16,
         pixel_width: u16,
         pixel_height: u16,
-    ) -> Result<(), String> {
-        let mut sessions = self.sessions.write().await;
-        if let Some(session_data) = sessions.get_mut(terminal_id) {
-            // Update session size
+    ) -> SSHResult<()> {
+        let (connection_id, running) = {
+            let mut sessions = self.sessions.write().await;
+            let session_data = sessions
+                .get_mut(terminal_id)
+                .ok_or_else(|| SSHError::not_found(ResourceKind::TerminalSession, terminal_id))?;
+
+            // Update session size; a PTY requested later picks it up
             session_data.session.resize(cols, rows, pixel_width, pixel_height);
+            (
+                session_data.session.connection_id.clone(),
+                session_data.channel_requests.is_some(),
+            )
+        };
 
-            // Resize SSH channel if available
-            if let Some(ref channel) = session_data.ssh_channel {
-                channel
-                    .window_change(cols as u32, rows as u32, pixel_width as u32, pixel_height as u32)
-                    .await
-                    .map_err(|e| format!("Failed to resize SSH channel: {}", e))?;
-            }
+        // Resize the remote PTY if the shell is running
+        if running {
+            let size = TerminalSize {
+                cols,
+                rows,
+                pixel_width,
+                pixel_height,
+            };
+            let (reply_sender, reply) = oneshot::channel();
+            self.send_request(terminal_id, ChannelRequest::Resize(size, reply_sender))
+                .await?;
+            reply.await.map_err(|_| channel_closed())??;
+        }
 
-            // Send resize event
-            let _ = self
-                .event_sender
-                .send(SSHEvent::TerminalResized(
-                    session_data.session.connection_id.clone(),
-                    terminal_id.to_string(),
-                    cols,
-                    rows,
-                ))
-                .await;
+        // Send resize event
+        let _ = self
+            .event_sender
+            .send(SSHEvent::TerminalResized(
+                connection_id,
+                terminal_id.to_string(),
+                cols,
+                rows,
+            ))
+            .await;
 
-            Ok(())
-        } else {
-            Err(format!("Terminal session {} not found", terminal_id))
-        }
+        Ok(())
     }
 
     /// Closes a terminal session
-    pub async fn close_session(&self, terminal_id: &str) -> Result<(), String> {
+    pub async fn close_session(&self, terminal_id: &str) -> SSHResult<()> {
         let mut sessions = self.sessions.write().await;
         if let Some(mut session_data) = sessions.remove(terminal_id) {
+            leave_broadcast_groups(&self.groups, terminal_id).await;
+
             // Deactivate session
             session_data.session.deactivate();
 
-            // Close SSH channel
+            // Close SSH channel, directly or through the I/O task that owns it
             if let Some(channel) = session_data.ssh_channel.take() {
                 let _ = channel.close().await;
             }
-
-            // Cancel I/O tasks
-            if let Some(task) = session_data.input_task.take() {
-                task.abort();
-            }
-            if let Some(task) = session_data.output_task.take() {
-                task.abort();
+            if let Some(channel_requests) = session_data.channel_requests.take() {
+                // A task stuck writing to a stalled channel can't take requests; stop it instead
+                if channel_requests.try_send(ChannelRequest::Close).is_err() {
+                    if let Some(task) = session_data.io_task.take() {
+                        task.abort();
+                    }
+                }
             }
 
             // Send terminal closed event
@@ -207,10 +499,38 @@ impl TerminalSessionManager {
 
             Ok(())
         } else {
-            Err(format!("Terminal session {} not found", terminal_id))
+            Err(SSHError::not_found(ResourceKind::TerminalSession, terminal_id))
         }
     }
 
+    /// Adds output from a session's channel to its scrollback and returns where the output
+    /// starts in everything the session has printed, or None if the session is gone
+    pub async fn record_output(&self, terminal_id: &str, data: &[u8]) -> Option<u64> {
+        let scrollback = self.scrollback(terminal_id).await?;
+        let offset = scrollback.lock().await.push(data);
+        Some(offset)
+    }
+
+    /// Returns a session's scrollback, oldest output first
+    pub async fn replay(&self, terminal_id: &str) -> SSHResult<TerminalReplay> {
+        let scrollback = self
+            .scrollback(terminal_id)
+            .await
+            .ok_or_else(|| SSHError::not_found(ResourceKind::TerminalSession, terminal_id))?;
+        let scrollback = scrollback.lock().await;
+        Ok(TerminalReplay {
+            terminal_id: terminal_id.to_string(),
+            data: scrollback.data.iter().copied().collect(),
+            truncated: scrollback.truncated,
+            offset: scrollback.offset,
+        })
+    }
+
+    async fn scrollback(&self, terminal_id: &str) -> Option<Arc<Mutex<Scrollback>>> {
+        let sessions = self.sessions.read().await;
+        sessions.get(terminal_id).map(|session_data| session_data.scrollback.clone())
+    }
+
     /// Gets information about a terminal session
     pub async fn get_session(&self, terminal_id: &str) -> Option<TerminalSession> {
         let sessions = self.sessions.read().await;
@@ -227,8 +547,69 @@ impl TerminalSessionManager {
             .collect()
     }
 
+    /// Detaches a connection's running sessions after its SSH session was lost. They keep
+    /// their id, size and TERM type, and are marked inactive until `reattach_session`
+    /// restarts them; sessions whose shell hadn't started yet are closed.
+    pub async fn detach_sessions_for_connection(&self, connection_id: &str) {
+        let mut unstarted = Vec::new();
+        {
+            let mut sessions = self.sessions.write().await;
+            for session_data in sessions.values_mut().filter(|data| data.session.connection_id == connection_id) {
+                if session_data.channel_requests.take().is_some() {
+                    if let Some(task) = session_data.io_task.take() {
+                        task.abort();
+                    }
+                    session_data.session.deactivate();
+                } else if session_data.session.is_active {
+                    unstarted.push(session_data.session.id.clone());
+                }
+            }
+        }
+
+        for terminal_id in unstarted {
+            let _ = self.close_session(&terminal_id).await;
+        }
+    }
+
+    /// Lists the ids of a connection's detached sessions
+    pub async fn detached_sessions_for_connection(&self, connection_id: &str) -> Vec<String> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .values()
+            .filter(|data| data.session.connection_id == connection_id && !data.session.is_active)
+            .map(|data| data.session.id.clone())
+            .collect()
+    }
+
+    /// Restarts a detached session on a channel of the re-established connection: a PTY
+    /// of the size and TERM type it had, then the user's shell. The session is closed if
+    /// either request fails.
+    pub async fn reattach_session(
+        &self,
+        terminal_id: &str,
+        channel: Channel<Msg>,
+        session_ended: SessionEnded,
+    ) -> SSHResult<()> {
+        let (size, term) = {
+            let mut sessions = self.sessions.write().await;
+            let session_data = sessions
+                .get_mut(terminal_id)
+                .ok_or_else(|| SSHError::not_found(ResourceKind::TerminalSession, terminal_id))?;
+            if session_data.session.is_active {
+                return Err(SSHError::InvalidState(format!("Terminal session {} is not detached", terminal_id)));
+            }
+
+            session_data.ssh_channel = Some(channel);
+            session_data.session_ended = session_ended;
+            session_data.session.is_active = true;
+            (session_data.session.size.clone(), session_data.session.term.clone())
+        };
+
+        self.start_session(terminal_id, size, term).await
+    }
+
     /// Closes all terminal sessions for a connection
-    pub async fn close_all_sessions_for_connection(&self, connection_id: &str) -> Result<(), String> {
+    pub async fn close_all_sessions_for_connection(&self, connection_id: &str) -> SSHResult<()> {
         let terminal_ids: Vec<String> = {
             let sessions = self.sessions.read().await;
             sessions
@@ -246,6 +627,112 @@ impl TerminalSessionManager {
     }
 }
 
+/// Hands input to a session's I/O task and waits for it to be written to the channel
+async fn send_data(channel_requests: mpsc::Sender<ChannelRequest>, data: Vec<u8>) -> SSHResult<()> {
+    let (reply_sender, reply) = oneshot::channel();
+    channel_requests
+        .send(ChannelRequest::Data(data, reply_sender))
+        .await
+        .map_err(|_| channel_closed())?;
+    reply.await.map_err(|_| channel_closed())?
+}
+
+/// Drops a closed terminal from every broadcast group
+async fn leave_broadcast_groups(groups: &RwLock<HashMap<String, BroadcastGroup>>, terminal_id: &str) {
+    for group in groups.write().await.values_mut() {
+        group.terminal_ids.retain(|id| id != terminal_id);
+    }
+}
+
+fn channel_closed() -> SSHError {
+    SSHError::Channel {
+        reason: "SSH channel closed".to_string(),
+    }
+}
+
+/// Waits for the server's answer to a channel request sent with `want_reply`
+async fn wait_for_reply(channel: &mut Channel<Msg>, request: &str) -> SSHResult<()> {
+    let reply = async {
+        while let Some(message) = channel.wait().await {
+            let reason = match message {
+                ChannelMsg::Success => return Ok(()),
+                ChannelMsg::Failure => format!("The server refused the {} request", request),
+                _ => continue,
+            };
+            return Err(SSHError::Channel { reason });
+        }
+        Err(SSHError::Channel {
+            reason: format!("The channel closed before the {} request was answered", request),
+        })
+    };
+
+    timeout(CHANNEL_REQUEST_TIMEOUT, reply)
+        .await
+        .map_err(|_| SSHError::Timeout {
+            operation: format!("waiting for the server to answer the {} request", request),
+        })?
+}
+
+/// Owns a running session's channel: forwards input and resizes to it, and closes the
+/// terminal session when the remote side closes the channel. If the channel went away
+/// because the connection was lost, the session is left for the connection to detach.
+async fn run_channel(
+    mut channel: Channel<Msg>,
+    mut requests: mpsc::Receiver<ChannelRequest>,
+    sessions: Arc<RwLock<HashMap<String, TerminalSessionData>>>,
+    groups: Arc<RwLock<HashMap<String, BroadcastGroup>>>,
+    event_sender: mpsc::Sender<SSHEvent>,
+    session_ended: SessionEnded,
+    terminal_id: String,
+) {
+    loop {
+        tokio::select! {
+            request = requests.recv() => match request {
+                Some(ChannelRequest::Data(data, reply)) => {
+                    let result = channel
+                        .data(&data[..])
+                        .await
+                        .map_err(|e| SSHError::channel("Failed to send data to SSH channel", e));
+                    let _ = reply.send(result);
+                }
+                Some(ChannelRequest::Resize(size, reply)) => {
+                    let result = channel
+                        .window_change(size.cols as u32, size.rows as u32, size.pixel_width as u32, size.pixel_height as u32)
+                        .await
+                        .map_err(|e| SSHError::channel("Failed to resize SSH channel", e));
+                    let _ = reply.send(result);
+                }
+                // Closed locally; the session is already gone
+                Some(ChannelRequest::Close) | None => {
+                    let _ = channel.close().await;
+                    return;
+                }
+            },
+            // Output reaches the UI through the connection handler's data callbacks;
+            // here we only watch for the channel going away
+            message = channel.wait() => {
+                if message.is_none() {
+                    break;
+                }
+            }
+        }
+    }
+
+    if session_ended.borrow().is_some() {
+        return;
+    }
+
+    // The shell exited or the server closed the channel
+    let mut sessions = sessions.write().await;
+    if let Some(removed) = sessions.remove(&terminal_id) {
+        leave_broadcast_groups(&groups, &terminal_id).await;
+        drop(sessions);
+        let _ = event_sender
+            .send(SSHEvent::TerminalClosed(removed.session.connection_id, terminal_id))
+            .await;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -320,8 +807,7 @@ mod tests {
         let manager = TerminalSessionManager::new(event_sender);
         
         let result = manager.close_session("nonexistent").await;
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert_eq!(result, Err(SSHError::not_found(ResourceKind::TerminalSession, "nonexistent")));
     }
 
     #[tokio::test]
@@ -330,8 +816,7 @@ mod tests {
         let manager = TerminalSessionManager::new(event_sender);
         
         let result = manager.send_input("nonexistent", b"test").await;
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert_eq!(result, Err(SSHError::not_found(ResourceKind::TerminalSession, "nonexistent")));
     }
 
     #[tokio::test]
@@ -340,8 +825,7 @@ mod tests {
         let manager = TerminalSessionManager::new(event_sender);
         
         let result = manager.resize_terminal("nonexistent", 80, 24, 640, 480).await;
-        assert!(result.is_err());
-        assert!(result.unwrap_err().contains("not found"));
+        assert_eq!(result, Err(SSHError::not_found(ResourceKind::TerminalSession, "nonexistent")));
     }
 
     #[tokio::test]
@@ -363,4 +847,192 @@ mod tests {
         let result = manager.close_all_sessions_for_connection("nonexistent").await;
         assert!(result.is_ok());
     }
-}
\ No newline at end of file
+
+    /// Registers a session whose input goes to `channel_requests`, as if its shell were running
+    async fn insert_session(manager: &TerminalSessionManager, channel_requests: Option<mpsc::Sender<ChannelRequest>>) -> String {
+        let session = TerminalSession::new("test-connection".to_string());
+        let terminal_id = session.id.clone();
+        let session_data = TerminalSessionData {
+            session,
+            ssh_channel: None,
+            channel_requests,
+            io_task: None,
+            session_ended: watch::channel(None).1,
+            scrollback: Arc::new(Mutex::new(Scrollback::new(ScrollbackConfig::default()))),
+        };
+        manager.sessions.write().await.insert(terminal_id.clone(), session_data);
+        terminal_id
+    }
+
+    /// Stands in for a session's I/O task, accepting input and passing it on
+    fn accept_input(received: mpsc::UnboundedSender<Vec<u8>>) -> mpsc::Sender<ChannelRequest> {
+        let (request_sender, mut requests) = mpsc::channel(CHANNEL_REQUEST_QUEUE);
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                if let ChannelRequest::Data(data, reply) = request {
+                    let _ = received.send(data);
+                    let _ = reply.send(Ok(()));
+                }
+            }
+        });
+        request_sender
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_group_membership() {
+        let (event_sender, _event_receiver) = mpsc::channel(10);
+        let manager = TerminalSessionManager::new(event_sender);
+        let first = insert_session(&manager, None).await;
+        let second = insert_session(&manager, None).await;
+
+        let group = manager
+            .create_broadcast_group("web".to_string(), vec![first.clone(), first.clone()])
+            .await
+            .unwrap();
+        assert_eq!(group.terminal_ids, vec![first.clone()]);
+
+        let group = manager.join_broadcast_group(&group.id, &second).await.unwrap();
+        assert_eq!(group.terminal_ids, vec![first.clone(), second.clone()]);
+        assert_eq!(manager.join_broadcast_group(&group.id, &second).await.unwrap(), group);
+        assert_eq!(
+            manager.join_broadcast_group(&group.id, "missing").await,
+            Err(SSHError::not_found(ResourceKind::TerminalSession, "missing"))
+        );
+        assert_eq!(
+            manager.join_broadcast_group("missing", &second).await,
+            Err(SSHError::not_found(ResourceKind::BroadcastGroup, "missing"))
+        );
+
+        let group = manager.leave_broadcast_group(&group.id, &first).await.unwrap();
+        assert_eq!(group.terminal_ids, vec![second.clone()]);
+        assert!(manager.leave_broadcast_group(&group.id, &first).await.is_err());
+
+        // Closed terminals leave their groups
+        manager.close_session(&second).await.unwrap();
+        assert!(manager.get_broadcast_group(&group.id).await.unwrap().terminal_ids.is_empty());
+
+        assert_eq!(manager.list_broadcast_groups().await, vec![manager.get_broadcast_group(&group.id).await.unwrap()]);
+        manager.delete_broadcast_group(&group.id).await.unwrap();
+        assert!(manager.list_broadcast_groups().await.is_empty());
+        assert_eq!(
+            manager.delete_broadcast_group(&group.id).await,
+            Err(SSHError::not_found(ResourceKind::BroadcastGroup, &group.id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reports_failing_members_and_reaches_the_rest() {
+        let (event_sender, _event_receiver) = mpsc::channel(10);
+        let manager = TerminalSessionManager::new(event_sender);
+        let (received_sender, mut received) = mpsc::unbounded_channel();
+        let first = insert_session(&manager, Some(accept_input(received_sender.clone()))).await;
+        let not_running = insert_session(&manager, None).await;
+        let (gone_sender, _) = mpsc::channel(1);
+        let gone = insert_session(&manager, Some(gone_sender)).await;
+        let last = insert_session(&manager, Some(accept_input(received_sender))).await;
+        let members = vec![first.clone(), not_running.clone(), gone.clone(), last.clone()];
+        let group = manager.create_broadcast_group("fleet".to_string(), members.clone()).await.unwrap();
+
+        let result = manager.broadcast_input(&group.id, b"uptime\n").await.unwrap();
+
+        assert_eq!(result.group_id, group.id);
+        assert_eq!(result.failed, 2);
+        assert_eq!(
+            result.deliveries.iter().map(|delivery| delivery.terminal_id.clone()).collect::<Vec<_>>(),
+            members
+        );
+        assert_eq!(result.deliveries[0].error, None);
+        assert_eq!(
+            result.deliveries[1].error,
+            Some(SSHError::Channel { reason: "SSH channel not available".to_string() })
+        );
+        assert_eq!(result.deliveries[2].error, Some(channel_closed()));
+        assert_eq!(result.deliveries[3].error, None);
+        assert_eq!(received.recv().await.unwrap(), b"uptime\n");
+        assert_eq!(received.recv().await.unwrap(), b"uptime\n");
+
+        assert_eq!(
+            manager.broadcast_input("missing", b"x").await,
+            Err(SSHError::not_found(ResourceKind::BroadcastGroup, "missing"))
+        );
+    }
+
+    fn scrollback_of(max_bytes: usize, max_lines: usize, output: &[&[u8]]) -> Scrollback {
+        let mut scrollback = Scrollback::new(ScrollbackConfig { max_bytes, max_lines });
+        for chunk in output {
+            scrollback.push(chunk);
+        }
+        scrollback
+    }
+
+    fn kept(scrollback: &Scrollback) -> Vec<u8> {
+        scrollback.data.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_scrollback_keeps_everything_within_limits() {
+        let scrollback = scrollback_of(64, 10, &[b"$ ls\r\n", b"a  b\r\n", b"$ "]);
+        assert_eq!(kept(&scrollback), b"$ ls\r\na  b\r\n$ ");
+        assert_eq!(scrollback.lines, 2);
+        assert!(!scrollback.truncated);
+    }
+
+    #[test]
+    fn test_scrollback_drops_oldest_output_past_the_byte_limit() {
+        let scrollback = scrollback_of(8, 10, &[b"0123", b"4567", b"89"]);
+        assert_eq!(kept(&scrollback), b"23456789");
+        assert!(scrollback.truncated);
+        // Dropped output still counts towards the offset
+        assert_eq!(scrollback.offset, 10);
+
+        // A chunk bigger than the buffer leaves only its tail
+        let scrollback = scrollback_of(4, 10, &[b"one\ntwo\nthree"]);
+        assert_eq!(kept(&scrollback), b"hree");
+        assert_eq!(scrollback.lines, 0);
+
+        // Nothing is kept when scrollback is off
+        let scrollback = scrollback_of(0, 10, &[b"one\n"]);
+        assert!(kept(&scrollback).is_empty());
+    }
+
+    #[test]
+    fn test_scrollback_drops_oldest_lines_past_the_line_limit() {
+        let scrollback = scrollback_of(1024, 2, &[b"one\ntwo\nthr", b"ee\nfour"]);
+        assert_eq!(kept(&scrollback), b"two\nthree\nfour");
+        assert_eq!(scrollback.lines, 2);
+        assert!(scrollback.truncated);
+    }
+
+    #[test]
+    fn test_scrollback_does_not_start_inside_a_character() {
+        // "é" is two bytes; the byte limit cuts between them
+        let scrollback = scrollback_of(4, 10, &["xé\nab".as_bytes()]);
+        assert_eq!(kept(&scrollback), b"\nab");
+    }
+
+    #[tokio::test]
+    async fn test_replay_returns_recorded_output() {
+        let (event_sender, _event_receiver) = mpsc::channel(10);
+        let manager = TerminalSessionManager::new(event_sender);
+        let terminal_id = insert_session(&manager, None).await;
+
+        assert_eq!(manager.record_output(&terminal_id, b"$ uptime\r\n").await, Some(0));
+        assert_eq!(manager.record_output(&terminal_id, b" 10:00  up 3 days\r\n").await, Some(10));
+        assert_eq!(manager.record_output("missing", b"ignored").await, None);
+
+        let replay = manager.replay(&terminal_id).await.unwrap();
+        assert_eq!(
+            replay,
+            TerminalReplay {
+                terminal_id: terminal_id.clone(),
+                data: b"$ uptime\r\n 10:00  up 3 days\r\n".to_vec(),
+                truncated: false,
+                offset: 29,
+            }
+        );
+        assert_eq!(
+            manager.replay("missing").await,
+            Err(SSHError::not_found(ResourceKind::TerminalSession, "missing"))
+        );
+    }
+}
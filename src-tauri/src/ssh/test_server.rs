@@ -0,0 +1,556 @@
+//! In-process SSH server the connection tests run against
+
+use crate::ssh::known_hosts::KnownHosts;
+use russh::keys::key::{KeyPair, PublicKey};
+use russh::keys::load_public_key;
+use russh::server::{self, Auth, Msg, Response, Session};
+use russh::{Channel, ChannelId, CryptoVec, MethodSet, Sig};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+pub const TEST_USERNAME: &str = "hana";
+pub const TEST_PASSWORD: &str = "correct horse battery staple";
+pub const TEST_KEY_PASSPHRASE: &str = "hunter2";
+
+/// Returns the path of a key pair under `tests/fixtures/keys`
+pub fn fixture_key_path(name: &str) -> String {
+    format!("{}/tests/fixtures/keys/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Loads the public half of a fixture key pair
+pub fn fixture_public_key(name: &str) -> PublicKey {
+    load_public_key(format!("{}.pub", fixture_key_path(name))).unwrap()
+}
+
+/// What the test server accepts
+#[derive(Clone, Default)]
+pub struct TestServerConfig {
+    pub password: Option<String>,
+    pub authorized_keys: Vec<PublicKey>,
+    /// Keyboard-interactive challenges as (prompt, echo, expected answer), one per round
+    pub keyboard_interactive: Vec<(&'static str, bool, &'static str)>,
+    /// Authentication methods offered to clients; all of them when `None`
+    pub methods: Option<MethodSet>,
+}
+
+/// A terminal-related request a client made on a session channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum TerminalRequest {
+    Pty { term: String, cols: u32, rows: u32 },
+    Shell,
+    WindowChange { cols: u32, rows: u32 },
+}
+
+/// Input that makes the test shell exit and close its channel
+pub const TEST_SHELL_EXIT: &[u8] = b"exit\n";
+
+/// Prefix that makes the test shell echo the rest of the input on stderr
+pub const TEST_SHELL_STDERR: &[u8] = b"stderr:";
+
+/// Exec command that prints a few megabytes to stdout and exits
+pub const TEST_EXEC_FLOOD: &str = "yes";
+
+/// Exec command that prints itself and then never exits
+pub const TEST_EXEC_HANG: &str = "sleep infinity";
+
+/// Tunnel target, or remote forward address, the server never answers a request for
+pub const TEST_TUNNEL_HANG_HOST: &str = "hang.invalid";
+
+/// Exec command that is killed by SIGTERM
+pub const TEST_EXEC_KILLED: &str = "kill -TERM $$";
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub host_key: PublicKey,
+    /// Targets of every `direct-tcpip` channel clients have opened, in order
+    pub tunnels: Arc<Mutex<Vec<SocketAddr>>>,
+    /// PTY, shell and window-change requests on session channels, in order
+    pub terminal_requests: Arc<Mutex<Vec<TerminalRequest>>>,
+    /// Every session the server has accepted, including ones that have since ended
+    sessions: Arc<Mutex<Vec<server::Handle>>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Starts a server on an ephemeral localhost port
+    pub async fn start(config: TestServerConfig) -> Self {
+        let host_key_pair = KeyPair::generate_ed25519().unwrap();
+        let host_key = host_key_pair.clone_public_key().unwrap();
+
+        let server_config = Arc::new(server::Config {
+            keys: vec![host_key_pair],
+            auth_rejection_time: std::time::Duration::from_millis(10),
+            auth_rejection_time_initial: Some(std::time::Duration::from_millis(0)),
+            methods: config.methods.unwrap_or(MethodSet::all()),
+            ..Default::default()
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Arc::new(config);
+        let tunnels = Arc::new(Mutex::new(Vec::new()));
+        let terminal_requests = Arc::new(Mutex::new(Vec::new()));
+
+        let handler_tunnels = tunnels.clone();
+        let handler_terminal_requests = terminal_requests.clone();
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let accepted_sessions = sessions.clone();
+        let accept_task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = TestServerHandler {
+                    config: config.clone(),
+                    keyboard_interactive_round: 0,
+                    tunnels: handler_tunnels.clone(),
+                    terminal_requests: handler_terminal_requests.clone(),
+                    shells: Vec::new(),
+                    remote_listeners: HashMap::new(),
+                };
+                if let Ok(session) = server::run_stream(server_config.clone(), stream, handler).await {
+                    accepted_sessions.lock().unwrap().push(session.handle());
+                }
+            }
+        });
+
+        Self {
+            addr,
+            host_key,
+            tunnels,
+            terminal_requests,
+            sessions,
+            accept_task,
+        }
+    }
+
+    /// Disconnects every client, as a server restart or a dropped network would
+    pub async fn disconnect_clients(&self) {
+        let sessions = std::mem::take(&mut *self.sessions.lock().unwrap());
+        for session in sessions {
+            let _ = session
+                .disconnect(russh::Disconnect::ByApplication, "server restarting".to_string(), String::new())
+                .await;
+        }
+    }
+
+    /// Returns a fresh known_hosts file that already trusts this server
+    pub fn known_hosts(&self) -> KnownHosts {
+        let known_hosts = empty_known_hosts();
+        known_hosts
+            .learn(&self.addr.ip().to_string(), self.addr.port(), &self.host_key)
+            .unwrap();
+        known_hosts
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+/// TCP relay in front of a server that can stop delivering anything while keeping both
+/// sides connected, like a network path that went dead without a reset
+pub struct TestRelay {
+    pub addr: SocketAddr,
+    /// Reads from the client discarded while silent. Sent a second or more apart, as
+    /// keepalive probes are, each arrives as a read of its own.
+    pub dropped_client_reads: Arc<AtomicUsize>,
+    silent: Arc<AtomicBool>,
+    accept_task: JoinHandle<()>,
+}
+
+impl TestRelay {
+    pub async fn start(target: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let silent = Arc::new(AtomicBool::new(false));
+        let dropped_client_reads = Arc::new(AtomicUsize::new(0));
+
+        let relay_silent = silent.clone();
+        let relay_dropped = dropped_client_reads.clone();
+        let accept_task = tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let Ok(server) = TcpStream::connect(target).await else {
+                    continue;
+                };
+                let (client_read, client_write) = client.into_split();
+                let (server_read, server_write) = server.into_split();
+                tokio::spawn(relay(client_read, server_write, relay_silent.clone(), relay_dropped.clone()));
+                tokio::spawn(relay(server_read, client_write, relay_silent.clone(), Arc::default()));
+            }
+        });
+
+        Self { addr, dropped_client_reads, silent, accept_task }
+    }
+
+    /// Drops everything sent in either direction from now on
+    pub fn go_silent(&self) {
+        self.silent.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for TestRelay {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+/// Copies one direction of a relayed connection, discarding the data once the relay is
+/// silent and counting the reads it discarded
+async fn relay<R, W>(mut from: R, mut to: W, silent: Arc<AtomicBool>, dropped: Arc<AtomicUsize>)
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0; 16 * 1024];
+    while let Ok(read) = from.read(&mut buffer).await {
+        if read == 0 {
+            break;
+        }
+        if silent.load(Ordering::SeqCst) {
+            dropped.fetch_add(1, Ordering::SeqCst);
+        } else if to.write_all(&buffer[..read]).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// In-process SSH agent listening on a Unix socket
+#[cfg(unix)]
+pub struct TestAgent {
+    pub socket_path: PathBuf,
+    serve_task: JoinHandle<()>,
+}
+
+#[cfg(unix)]
+impl TestAgent {
+    /// Starts an agent preloaded with the given identities, in order
+    pub async fn start(identities: Vec<KeyPair>) -> Self {
+        Self::serve(identities, true).await
+    }
+
+    /// Starts an agent that lists the given identities but refuses to sign with them, as
+    /// when the user declines a confirmation prompt or a hardware key is unplugged
+    pub async fn refusing_to_sign(identities: Vec<KeyPair>) -> Self {
+        Self::serve(identities, false).await
+    }
+
+    async fn serve(identities: Vec<KeyPair>, sign: bool) -> Self {
+        use russh::keys::agent::client::AgentClient;
+        use russh::keys::agent::server::MessageType;
+        use tokio_stream::wrappers::UnixListenerStream;
+
+        #[derive(Clone)]
+        struct TestAgentPolicy {
+            sign: bool,
+        }
+
+        #[async_trait::async_trait]
+        impl russh::keys::agent::server::Agent for TestAgentPolicy {
+            async fn confirm_request(&self, msg: MessageType) -> bool {
+                self.sign || !matches!(msg, MessageType::Sign)
+            }
+        }
+
+        let socket_dir = std::env::temp_dir().join(format!("hana-agent-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&socket_dir).unwrap();
+        let socket_path = socket_dir.join("agent.sock");
+
+        let listener = tokio::net::UnixListener::bind(&socket_path).unwrap();
+        let serve_task = tokio::spawn(async move {
+            let _ = russh::keys::agent::server::serve(UnixListenerStream::new(listener), TestAgentPolicy { sign }).await;
+        });
+
+        let mut client = AgentClient::connect_uds(&socket_path).await.unwrap();
+        for identity in &identities {
+            client.add_identity(identity, &[]).await.unwrap();
+        }
+
+        Self {
+            socket_path,
+            serve_task,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for TestAgent {
+    fn drop(&mut self) {
+        self.serve_task.abort();
+    }
+}
+
+/// Returns a known_hosts location in a fresh temporary directory
+pub fn empty_known_hosts() -> KnownHosts {
+    let path: PathBuf = std::env::temp_dir()
+        .join(format!("hana-test-server-{}", uuid::Uuid::new_v4()))
+        .join("known_hosts");
+    KnownHosts::new(path)
+}
+
+struct TestServerHandler {
+    config: Arc<TestServerConfig>,
+    keyboard_interactive_round: usize,
+    tunnels: Arc<Mutex<Vec<SocketAddr>>>,
+    terminal_requests: Arc<Mutex<Vec<TerminalRequest>>>,
+    /// Session channels running the test shell; other channels (tunnels) aren't echoed
+    shells: Vec<ChannelId>,
+    /// Listeners for the client's remote forwards, by the address and port it asked for
+    remote_listeners: HashMap<(String, u32), JoinHandle<()>>,
+}
+
+impl Drop for TestServerHandler {
+    fn drop(&mut self) {
+        for (_, listener) in self.remote_listeners.drain() {
+            listener.abort();
+        }
+    }
+}
+
+impl TestServerHandler {
+    fn keyboard_interactive_challenge(&self) -> Auth {
+        let (prompt, echo, _) = self.config.keyboard_interactive[self.keyboard_interactive_round];
+        Auth::Partial {
+            name: Cow::Borrowed("Test challenge"),
+            instructions: Cow::Owned(format!("Round {}", self.keyboard_interactive_round + 1)),
+            prompts: Cow::Owned(vec![(Cow::Borrowed(prompt), echo)]),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl server::Handler for TestServerHandler {
+    type Error = russh::Error;
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        if user == TEST_USERNAME && self.config.password.as_deref() == Some(password) {
+            Ok(Auth::Accept)
+        } else {
+            Ok(Auth::Reject { proceed_with_methods: None })
+        }
+    }
+
+    async fn auth_publickey(&mut self, user: &str, public_key: &PublicKey) -> Result<Auth, Self::Error> {
+        if user == TEST_USERNAME && self.config.authorized_keys.contains(public_key) {
+            Ok(Auth::Accept)
+        } else {
+            Ok(Auth::Reject { proceed_with_methods: None })
+        }
+    }
+
+    async fn auth_keyboard_interactive(
+        &mut self,
+        user: &str,
+        _submethods: &str,
+        response: Option<Response<'async_trait>>,
+    ) -> Result<Auth, Self::Error> {
+        let reject = Auth::Reject { proceed_with_methods: None };
+        if user != TEST_USERNAME || self.config.keyboard_interactive.is_empty() {
+            return Ok(reject);
+        }
+
+        let Some(mut response) = response else {
+            self.keyboard_interactive_round = 0;
+            return Ok(self.keyboard_interactive_challenge());
+        };
+
+        let (_, _, expected) = self.config.keyboard_interactive[self.keyboard_interactive_round];
+        if response.next() != Some(expected.as_bytes()) {
+            return Ok(reject);
+        }
+
+        self.keyboard_interactive_round += 1;
+        if self.keyboard_interactive_round < self.config.keyboard_interactive.len() {
+            Ok(self.keyboard_interactive_challenge())
+        } else {
+            Ok(Auth::Accept)
+        }
+    }
+
+    async fn channel_open_session(&mut self, _channel: Channel<Msg>, _session: &mut Session) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        term: &str,
+        col_width: u32,
+        row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(russh::Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.terminal_requests.lock().unwrap().push(TerminalRequest::Pty {
+            term: term.to_string(),
+            cols: col_width,
+            rows: row_height,
+        });
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    async fn shell_request(&mut self, channel: ChannelId, session: &mut Session) -> Result<(), Self::Error> {
+        self.terminal_requests.lock().unwrap().push(TerminalRequest::Shell);
+        self.shells.push(channel);
+        session.channel_success(channel);
+        Ok(())
+    }
+
+    async fn window_change_request(
+        &mut self,
+        _channel: ChannelId,
+        col_width: u32,
+        row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.terminal_requests.lock().unwrap().push(TerminalRequest::WindowChange {
+            cols: col_width,
+            rows: row_height,
+        });
+        Ok(())
+    }
+
+    /// The test shell echoes its input (on stderr after `TEST_SHELL_STDERR`), and exits on `TEST_SHELL_EXIT`
+    async fn data(&mut self, channel: ChannelId, data: &[u8], session: &mut Session) -> Result<(), Self::Error> {
+        if !self.shells.contains(&channel) {
+            return Ok(());
+        }
+
+        if data == TEST_SHELL_EXIT {
+            session.exit_status_request(channel, 0);
+            session.eof(channel);
+            session.close(channel);
+        } else if let Some(error) = data.strip_prefix(TEST_SHELL_STDERR) {
+            session.extended_data(channel, 1, CryptoVec::from_slice(error));
+        } else {
+            session.data(channel, CryptoVec::from_slice(data));
+        }
+        Ok(())
+    }
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        host_to_connect: &str,
+        port_to_connect: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut Session,
+    ) -> Result<bool, Self::Error> {
+        if host_to_connect == TEST_TUNNEL_HANG_HOST {
+            std::future::pending::<()>().await;
+        }
+        // Refuse the channel if the target can't be reached, as sshd does
+        let Ok(mut target) = tokio::net::TcpStream::connect((host_to_connect, port_to_connect as u16)).await else {
+            return Ok(false);
+        };
+        if let Ok(addr) = target.peer_addr() {
+            self.tunnels.lock().unwrap().push(addr);
+        }
+
+        tokio::spawn(async move {
+            let mut channel = channel.into_stream();
+            let _ = tokio::io::copy_bidirectional(&mut channel, &mut target).await;
+        });
+        Ok(true)
+    }
+
+    /// Listens as sshd does for `-R`, opening a `forwarded-tcpip` channel for each connection
+    async fn tcpip_forward(&mut self, address: &str, port: &mut u32, session: &mut Session) -> Result<bool, Self::Error> {
+        if address == TEST_TUNNEL_HANG_HOST {
+            std::future::pending::<()>().await;
+        }
+        let Ok(listener) = TcpListener::bind((address, *port as u16)).await else {
+            return Ok(false);
+        };
+        let Ok(local_addr) = listener.local_addr() else {
+            return Ok(false);
+        };
+        *port = local_addr.port() as u32;
+
+        let handle = session.handle();
+        let (connected_address, connected_port) = (address.to_string(), *port);
+        let listener_task = tokio::spawn(async move {
+            while let Ok((mut stream, originator)) = listener.accept().await {
+                let Ok(channel) = handle
+                    .channel_open_forwarded_tcpip(
+                        connected_address.clone(),
+                        connected_port,
+                        originator.ip().to_string(),
+                        originator.port() as u32,
+                    )
+                    .await
+                else {
+                    continue;
+                };
+                tokio::spawn(async move {
+                    let mut channel = channel.into_stream();
+                    let _ = tokio::io::copy_bidirectional(&mut channel, &mut stream).await;
+                });
+            }
+        });
+        self.remote_listeners.insert((address.to_string(), *port), listener_task);
+        Ok(true)
+    }
+
+    async fn cancel_tcpip_forward(&mut self, address: &str, port: u32, _session: &mut Session) -> Result<bool, Self::Error> {
+        let Some(listener_task) = self.remote_listeners.remove(&(address.to_string(), port)) else {
+            return Ok(false);
+        };
+        listener_task.abort();
+        Ok(true)
+    }
+
+    /// Runs the test exec commands above; `exit N` prints to stderr and exits with status N,
+    /// and any other command prints itself and exits 0
+    async fn exec_request(&mut self, channel: ChannelId, data: &[u8], session: &mut Session) -> Result<(), Self::Error> {
+        let command = String::from_utf8_lossy(data).into_owned();
+        session.channel_success(channel);
+
+        match command.as_str() {
+            TEST_EXEC_HANG => {
+                session.data(channel, CryptoVec::from_slice(format!("{}\n", command).as_bytes()));
+                return Ok(());
+            }
+            TEST_EXEC_KILLED => {
+                session.exit_signal_request(channel, Sig::TERM, false, "Terminated", "");
+            }
+            TEST_EXEC_FLOOD => {
+                let handle = session.handle();
+                tokio::spawn(async move {
+                    for _ in 0..1024 {
+                        if handle.data(channel, CryptoVec::from_slice(&[b'y'; 4096])).await.is_err() {
+                            return;
+                        }
+                    }
+                    let _ = handle.exit_status_request(channel, 0).await;
+                    let _ = handle.close(channel).await;
+                });
+                return Ok(());
+            }
+            _ => match command.strip_prefix("exit ").and_then(|status| status.parse().ok()) {
+                Some(status) => {
+                    session.extended_data(channel, 1, CryptoVec::from_slice(format!("exiting with {}\n", status).as_bytes()));
+                    session.exit_status_request(channel, status);
+                }
+                None => {
+                    session.data(channel, CryptoVec::from_slice(format!("{}\n", command).as_bytes()));
+                    session.exit_status_request(channel, 0);
+                }
+            },
+        }
+        session.eof(channel);
+        session.close(channel);
+        Ok(())
+    }
+}
@@ -1,21 +1,287 @@
-use serde::{Deserialize, Serialize};
+use crate::ssh::credentials::{CredentialRef, CredentialStore, SecretKind};
+use crate::ssh::error::{SSHError, SSHResult};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
 use std::net::{IpAddr, ToSocketAddrs};
 use std::path::Path;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SSHConnectionConfig {
     pub id: String,
     pub name: String,
     pub hostname: String,
     pub port: u16,
     pub username: String,
-    pub auth_method: AuthMethod,
+    /// Authentication methods to try, in order, until one succeeds. A single
+    /// `auth_method` value is still accepted from older configurations.
+    #[serde(alias = "auth_method", deserialize_with = "deserialize_auth_methods")]
+    pub auth_methods: Vec<AuthMethod>,
     pub private_key_path: Option<String>,
+    /// Write-only: moved into the keyring by `store_secrets` and never serialized back out
+    #[serde(default, skip_serializing)]
     pub password: Option<String>,
+    /// Write-only: moved into the keyring by `store_secrets` and never serialized back out
+    #[serde(default, skip_serializing)]
+    pub key_passphrase: Option<String>,
+    #[serde(default)]
+    pub password_ref: Option<CredentialRef>,
+    #[serde(default)]
+    pub key_passphrase_ref: Option<CredentialRef>,
+    /// Seconds to wait for the TCP connection; `DEFAULT_CONNECT_TIMEOUT_SECS` when unset
+    #[serde(default)]
+    pub connect_timeout_secs: Option<u64>,
+    /// Ids of saved connections to tunnel through, outermost first (OpenSSH's ProxyJump)
+    #[serde(default)]
+    pub jump_hosts: Vec<String>,
+    /// What to do when an established connection drops
+    #[serde(default)]
+    pub reconnect: ReconnectPolicy,
+    /// How a connection that stopped answering is noticed
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    /// Local port forwards saved with the host (OpenSSH's LocalForward)
+    #[serde(default)]
+    pub local_forwards: Vec<LocalForward>,
+    /// Remote port forwards saved with the host (OpenSSH's RemoteForward)
+    #[serde(default)]
+    pub remote_forwards: Vec<RemoteForward>,
+    /// SOCKS proxies saved with the host (OpenSSH's DynamicForward)
+    #[serde(default)]
+    pub dynamic_forwards: Vec<DynamicForward>,
+    /// Labels for picking out groups of hosts, such as `prod` or `db`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// How much of each terminal's output is kept for replay
+    #[serde(default)]
+    pub scrollback: ScrollbackConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Connect timeout used when a configuration doesn't set one
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 15;
+
+/// `keepalive@openssh.com` probes sent when the server has been quiet for `interval_secs`,
+/// like OpenSSH's ServerAliveInterval and ServerAliveCountMax. Once `max_missed` probes
+/// in a row go unanswered the connection is treated as dead, when the next one is due.
+/// At least two are required, since russh has no way to give up after a single probe.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    /// Seconds of silence before a probe is sent; 0 disables probing
+    pub interval_secs: u64,
+    pub max_missed: u32,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 15,
+            max_missed: 3,
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    /// Returns how long the server may be quiet before a probe is sent, if probing is enabled
+    pub fn interval(&self) -> Option<std::time::Duration> {
+        (self.interval_secs > 0).then(|| std::time::Duration::from_secs(self.interval_secs))
+    }
+
+    /// The `keepalive_max` russh needs for `max_missed` probes: it only gives up once more
+    /// than that many are outstanding, and 0 would mean never
+    pub fn russh_max(&self) -> usize {
+        self.max_missed.saturating_sub(1) as usize
+    }
+
+    fn validate(&self, errors: &mut Vec<ValidationError>) {
+        if self.interval_secs > 0 && self.max_missed < 2 {
+            errors.push(ValidationError {
+                field: "keepalive.max_missed".to_string(),
+                message: "At least two keepalive probes must be allowed to go unanswered".to_string(),
+            });
+        }
+    }
+}
+
+/// Limits on the output kept for each terminal, so a view mounted later (after a tab
+/// switch, or in a detached window) can be filled in with what it missed. The oldest
+/// output is dropped first once either limit is reached.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ScrollbackConfig {
+    /// Bytes of raw output kept; 0 keeps none
+    pub max_bytes: usize,
+    /// Complete lines kept, not counting the one being written
+    pub max_lines: usize,
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 1024,
+            max_lines: 10_000,
+        }
+    }
+}
+
+impl ScrollbackConfig {
+    fn validate(&self, errors: &mut Vec<ValidationError>) {
+        if self.max_bytes > 0 && self.max_lines == 0 {
+            errors.push(ValidationError {
+                field: "scrollback.max_lines".to_string(),
+                message: "Scrollback must keep at least one line".to_string(),
+            });
+        }
+    }
+}
+
+/// How a dropped connection is re-established
+///
+/// The delay before attempt `n` is `initial_delay_ms * multiplier^(n-1)`, capped at
+/// `max_delay_ms`, with up to `jitter` of it taken off at random so clients that
+/// dropped together don't all come back at once.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    /// Attempts before giving up and reporting the connection as failed
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of each delay that is randomised, from 0 (none) to 1
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 10,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns how long to wait before the given attempt (counting from 1), where
+    /// `random` is a number in `[0, 1)` choosing how much jitter to take off
+    pub fn delay(&self, attempt: u32, random: f64) -> std::time::Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = (self.initial_delay_ms as f64 * self.multiplier.powi(exponent)).min(self.max_delay_ms as f64);
+        let jitter = backoff * self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0);
+        std::time::Duration::from_millis((backoff - jitter).max(0.0) as u64)
+    }
+
+    /// Like `delay`, with fresh randomness
+    pub fn next_delay(&self, attempt: u32) -> std::time::Duration {
+        self.delay(attempt, random_fraction())
+    }
+
+    fn validate(&self, errors: &mut Vec<ValidationError>) {
+        if self.max_delay_ms < self.initial_delay_ms {
+            errors.push(ValidationError {
+                field: "reconnect.max_delay_ms".to_string(),
+                message: "Maximum reconnect delay cannot be less than the initial delay".to_string(),
+            });
+        }
+        if self.multiplier.is_nan() || self.multiplier < 1.0 {
+            errors.push(ValidationError {
+                field: "reconnect.multiplier".to_string(),
+                message: "Reconnect backoff multiplier must be at least 1".to_string(),
+            });
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            errors.push(ValidationError {
+                field: "reconnect.jitter".to_string(),
+                message: "Reconnect jitter must be between 0 and 1".to_string(),
+            });
+        }
+    }
+}
+
+/// A random number in `[0, 1)`, drawn from the low bits of a v4 UUID, which are all random
+fn random_fraction() -> f64 {
+    let bits = uuid::Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1);
+    bits as f64 / (1u64 << 53) as f64
+}
+
+impl std::fmt::Debug for SSHConnectionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = |secret: &Option<String>| secret.as_ref().map(|_| "<redacted>");
+
+        f.debug_struct("SSHConnectionConfig")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("hostname", &self.hostname)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("auth_methods", &self.auth_methods)
+            .field("private_key_path", &self.private_key_path)
+            .field("password", &redacted(&self.password))
+            .field("key_passphrase", &redacted(&self.key_passphrase))
+            .field("password_ref", &self.password_ref)
+            .field("key_passphrase_ref", &self.key_passphrase_ref)
+            .field("connect_timeout_secs", &self.connect_timeout_secs)
+            .field("jump_hosts", &self.jump_hosts)
+            .field("reconnect", &self.reconnect)
+            .field("keepalive", &self.keepalive)
+            .field("local_forwards", &self.local_forwards)
+            .field("remote_forwards", &self.remote_forwards)
+            .field("dynamic_forwards", &self.dynamic_forwards)
+            .field("tags", &self.tags)
+            .field("scrollback", &self.scrollback)
+            .finish()
+    }
+}
+
+/// An empty configuration on port 22, to fill in with struct update syntax
+impl Default for SSHConnectionConfig {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            name: String::new(),
+            hostname: String::new(),
+            port: 22,
+            username: String::new(),
+            auth_methods: Vec::new(),
+            private_key_path: None,
+            password: None,
+            key_passphrase: None,
+            password_ref: None,
+            key_passphrase_ref: None,
+            connect_timeout_secs: None,
+            jump_hosts: Vec::new(),
+            reconnect: ReconnectPolicy::default(),
+            keepalive: KeepaliveConfig::default(),
+            local_forwards: Vec::new(),
+            remote_forwards: Vec::new(),
+            dynamic_forwards: Vec::new(),
+            tags: Vec::new(),
+            scrollback: ScrollbackConfig::default(),
+        }
+    }
+}
+
+/// Accepts either a single authentication method or an ordered list of them
+fn deserialize_auth_methods<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<AuthMethod>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(AuthMethod),
+        Many(Vec<AuthMethod>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(method) => vec![method],
+        OneOrMany::Many(methods) => methods,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ValidationError {
     pub field: String,
     pub message: String,
@@ -34,6 +300,7 @@ pub enum AuthMethod {
     Password,
     PublicKey,
     Agent,
+    KeyboardInteractive,
 }
 
 impl AuthMethod {
@@ -43,6 +310,7 @@ impl AuthMethod {
             AuthMethod::Password => "Password authentication",
             AuthMethod::PublicKey => "Public key authentication",
             AuthMethod::Agent => "SSH agent authentication",
+            AuthMethod::KeyboardInteractive => "Keyboard-interactive authentication",
         }
     }
 
@@ -57,12 +325,43 @@ impl AuthMethod {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Why one method in the authentication chain did not authenticate the session
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuthFailure {
+    pub method: AuthMethod,
+    pub reason: String,
+}
+
+/// Outcome of walking the authentication chain
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AuthReport {
+    /// The method that authenticated the session, if any did
+    pub method: Option<AuthMethod>,
+    /// Methods tried before it (or all of them on failure), in order
+    pub failures: Vec<AuthFailure>,
+}
+
+impl AuthReport {
+    /// Summarises every failed attempt, e.g. for an error message
+    pub fn failure_summary(&self) -> String {
+        self.failures
+            .iter()
+            .map(|failure| format!("{}: {}", failure.method.description(), failure.reason))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct SSHConnectionState {
     pub id: String,
     pub status: ConnectionStatus,
-    pub error: Option<String>,
+    pub error: Option<SSHError>,
     pub last_activity: chrono::DateTime<chrono::Utc>,
+    /// The SSH agent identity that authenticated the session, if any
+    pub auth_identity: Option<String>,
+    /// Which authentication method succeeded and why earlier ones failed
+    pub auth_report: AuthReport,
 }
 
 impl SSHConnectionState {
@@ -73,6 +372,8 @@ impl SSHConnectionState {
             status: ConnectionStatus::Disconnected,
             error: None,
             last_activity: chrono::Utc::now(),
+            auth_identity: None,
+            auth_report: AuthReport::default(),
         }
     }
 
@@ -83,8 +384,8 @@ impl SSHConnectionState {
         self.last_activity = chrono::Utc::now();
     }
 
-    /// Sets an error state with the given error message
-    pub fn set_error(&mut self, error: String) {
+    /// Sets an error state with the given error
+    pub fn set_error(&mut self, error: SSHError) {
         self.status = ConnectionStatus::Error;
         self.error = Some(error);
         self.last_activity = chrono::Utc::now();
@@ -95,9 +396,9 @@ impl SSHConnectionState {
         self.last_activity = chrono::Utc::now();
     }
 
-    /// Checks if the connection is currently active (connected or connecting)
+    /// Checks if the connection is currently active (connected, connecting or reconnecting)
     pub fn is_active(&self) -> bool {
-        matches!(self.status, ConnectionStatus::Connected | ConnectionStatus::Connecting)
+        self.status.is_active()
     }
 
     /// Checks if the connection has an error
@@ -110,6 +411,8 @@ impl SSHConnectionState {
 pub enum ConnectionStatus {
     Connecting,
     Connected,
+    /// The connection dropped and is being re-established; `attempt` counts from 1
+    Reconnecting { attempt: u32 },
     Disconnected,
     Error,
 }
@@ -117,7 +420,10 @@ pub enum ConnectionStatus {
 impl ConnectionStatus {
     /// Returns true if the status represents an active connection
     pub fn is_active(&self) -> bool {
-        matches!(self, ConnectionStatus::Connected | ConnectionStatus::Connecting)
+        matches!(
+            self,
+            ConnectionStatus::Connected | ConnectionStatus::Connecting | ConnectionStatus::Reconnecting { .. }
+        )
     }
 
     /// Returns true if the status represents a terminal state (error or disconnected)
@@ -130,6 +436,7 @@ impl ConnectionStatus {
         match self {
             ConnectionStatus::Connecting => "Establishing connection...",
             ConnectionStatus::Connected => "Connected",
+            ConnectionStatus::Reconnecting { .. } => "Connection lost, reconnecting...",
             ConnectionStatus::Disconnected => "Disconnected",
             ConnectionStatus::Error => "Connection error",
         }
@@ -140,18 +447,53 @@ impl ConnectionStatus {
 pub enum SSHEvent {
     Connected(String),
     Disconnected(String),
-    Data(String, Vec<u8>),
-    Error(String, String),
+    Reconnecting(String, u32, u64), // connection_id, attempt, delay in ms before it
+    Data(String, String, OutputStream, u64, Vec<u8>), // connection_id, terminal_id, stream, offset, data
+    Error(String, SSHError),
     TerminalCreated(String, String), // connection_id, terminal_id
     TerminalClosed(String, String),  // connection_id, terminal_id
     TerminalResized(String, String, u16, u16), // connection_id, terminal_id, cols, rows
+    HostKeyUnknown(String, String, u16, String, String), // connection_id, hostname, port, key_type, fingerprint
+    HostKeyChanged(String, String, u16, String, String), // connection_id, hostname, port, known fingerprint, presented fingerprint
+    KeyPassphraseRequired(String, String), // connection_id, key_path
+    KeyboardInteractive(String, KeyboardInteractiveChallenge), // connection_id, challenge
+    BatchHostStarted(String, String), // batch_id, connection_id
+    BatchHostFinished(String, usize, usize, Box<BatchHostResult>), // batch_id, hosts finished, hosts in the batch, result
+}
+
+/// Which of a channel's output streams data arrived on
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A single prompt within a keyboard-interactive challenge
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyboardInteractivePrompt {
+    pub prompt: String,
+    /// Whether the user's answer may be shown as it is typed
+    pub echo: bool,
+}
+
+/// An information request sent by the server during keyboard-interactive authentication
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyboardInteractiveChallenge {
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<KeyboardInteractivePrompt>,
 }
 
+/// TERM type requested for a PTY when the caller doesn't name one
+pub const DEFAULT_TERM: &str = "xterm-256color";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TerminalSession {
     pub id: String,
     pub connection_id: String,
     pub size: TerminalSize,
+    /// TERM type the PTY was requested with
+    pub term: String,
     pub is_active: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
@@ -181,6 +523,7 @@ impl TerminalSession {
             id: uuid::Uuid::new_v4().to_string(),
             connection_id,
             size: TerminalSize::default(),
+            term: DEFAULT_TERM.to_string(),
             is_active: true,
             created_at: chrono::Utc::now(),
         }
@@ -198,6 +541,392 @@ impl TerminalSession {
     }
 }
 
+/// Serializes raw terminal output as a base64 string rather than an array of numbers
+pub fn serialize_base64<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64_STANDARD.encode(data))
+}
+
+/// A terminal's kept output, for a view that wasn't mounted when it arrived
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct TerminalReplay {
+    pub terminal_id: String,
+    /// Raw output, stdout and stderr interleaved as they arrived; base64 when serialized
+    #[serde(serialize_with = "serialize_base64")]
+    pub data: Vec<u8>,
+    /// Whether older output was dropped to stay within the scrollback limits
+    pub truncated: bool,
+    /// Bytes the terminal has printed in all, up to the end of `data`. Output events below
+    /// this offset are already part of the replay.
+    pub offset: u64,
+}
+
+/// Terminals that all receive what is typed into any one of them
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BroadcastGroup {
+    pub id: String,
+    pub name: String,
+    /// Member terminals, in the order they joined
+    pub terminal_ids: Vec<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl BroadcastGroup {
+    pub fn new(name: String) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            terminal_ids: Vec::new(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+}
+
+/// Whether broadcast input reached one member terminal
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BroadcastDelivery {
+    pub terminal_id: String,
+    /// Why the input couldn't be sent to the terminal
+    pub error: Option<SSHError>,
+}
+
+/// The outcome of broadcasting input, one row per member in the group's order
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BroadcastResult {
+    pub group_id: String,
+    pub deliveries: Vec<BroadcastDelivery>,
+    /// Members the input couldn't be sent to
+    pub failed: usize,
+}
+
+/// Address local forwards listen on when a rule doesn't name one
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+
+/// A local (`-L`) port forward: each connection to `bind_address:bind_port` is carried
+/// over the SSH connection to `target_host:target_port`, as seen from the server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LocalForward {
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// 0 picks a free port
+    pub bind_port: u16,
+    pub target_host: String,
+    pub target_port: u16,
+    /// Start the forward whenever the host connects
+    #[serde(default)]
+    pub auto_start: bool,
+}
+
+fn default_bind_address() -> String {
+    DEFAULT_BIND_ADDRESS.to_string()
+}
+
+impl LocalForward {
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        validate_forward(&self.bind_address, &self.target_host, self.target_port)
+    }
+}
+
+/// Address the server listens on for remote forwards when a rule doesn't name one.
+/// Like OpenSSH, only the server's loopback interface is used by default.
+pub const DEFAULT_REMOTE_BIND_ADDRESS: &str = "localhost";
+
+/// A remote (`-R`) port forward: the server listens on `bind_address:bind_port` and each
+/// connection made to it there is carried back and connected to `target_host:target_port`,
+/// as seen from this machine
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RemoteForward {
+    #[serde(default = "default_remote_bind_address")]
+    pub bind_address: String,
+    /// 0 lets the server pick a free port
+    pub bind_port: u16,
+    pub target_host: String,
+    pub target_port: u16,
+    /// Start the forward whenever the host connects
+    #[serde(default)]
+    pub auto_start: bool,
+}
+
+fn default_remote_bind_address() -> String {
+    DEFAULT_REMOTE_BIND_ADDRESS.to_string()
+}
+
+impl RemoteForward {
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        validate_forward(&self.bind_address, &self.target_host, self.target_port)
+    }
+}
+
+/// A dynamic (`-D`) port forward: a SOCKS proxy listening on `bind_address:bind_port`
+/// that carries each connection over the SSH connection to wherever the client asks,
+/// as seen from the server
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DynamicForward {
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    /// 0 picks a free port
+    pub bind_port: u16,
+    /// Start the proxy whenever the host connects
+    #[serde(default)]
+    pub auto_start: bool,
+}
+
+impl DynamicForward {
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        if self.bind_address.trim().is_empty() {
+            Err(vec![ValidationError {
+                field: "bind_address".to_string(),
+                message: "Bind address cannot be empty".to_string(),
+            }])
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Checks the fields local and remote forward rules have in common
+fn validate_forward(bind_address: &str, target_host: &str, target_port: u16) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+
+    if bind_address.trim().is_empty() {
+        errors.push(ValidationError {
+            field: "bind_address".to_string(),
+            message: "Bind address cannot be empty".to_string(),
+        });
+    }
+    if target_host.trim().is_empty() {
+        errors.push(ValidationError {
+            field: "target_host".to_string(),
+            message: "Target host cannot be empty".to_string(),
+        });
+    }
+    if target_port == 0 {
+        errors.push(ValidationError {
+            field: "target_port".to_string(),
+            message: "Target port must be greater than 0".to_string(),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// The rule a running port forward was started from, serialized tagged by its kind,
+/// such as `{"Local": {...}}`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum ForwardRule {
+    Local(LocalForward),
+    Remote(RemoteForward),
+    Dynamic(DynamicForward),
+}
+
+impl ForwardRule {
+    /// Whether the rule is started whenever its host connects
+    pub fn auto_start(&self) -> bool {
+        match self {
+            ForwardRule::Local(rule) => rule.auto_start,
+            ForwardRule::Remote(rule) => rule.auto_start,
+            ForwardRule::Dynamic(rule) => rule.auto_start,
+        }
+    }
+}
+
+/// A running port forward, as every forward command returns it whatever its kind
+#[derive(Debug, Clone, Serialize)]
+pub struct PortForward {
+    pub id: String,
+    pub connection_id: String,
+    pub rule: ForwardRule,
+    /// The address actually listened on, with the port filled in: on the server for a
+    /// remote forward, on this machine for the others
+    pub listen_address: String,
+    pub stats: ForwardStats,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Traffic through a port forward since it started
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ForwardStats {
+    /// Bytes read on this machine and sent through the tunnel
+    pub bytes_sent: u64,
+    /// Bytes received through the tunnel and written on this machine
+    pub bytes_received: u64,
+    /// Connections accepted so far
+    pub connections: u64,
+    /// Connections currently being forwarded
+    pub active_connections: u64,
+}
+
+/// How long a command may run when a request doesn't say
+pub const DEFAULT_EXEC_TIMEOUT_SECS: u64 = 60;
+
+/// How much output is kept from a command when a request doesn't say
+pub const DEFAULT_EXEC_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// A one-off command to run on a connection, outside any terminal
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExecRequest {
+    pub command: String,
+    /// Seconds before the command is abandoned; `DEFAULT_EXEC_TIMEOUT_SECS` when unset
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Bytes of stdout and stderr, together, kept before the command is stopped;
+    /// `DEFAULT_EXEC_MAX_OUTPUT_BYTES` when unset
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+}
+
+impl ExecRequest {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            timeout_secs: None,
+            max_output_bytes: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = Vec::new();
+
+        if self.command.trim().is_empty() {
+            errors.push(ValidationError {
+                field: "command".to_string(),
+                message: "Command cannot be empty".to_string(),
+            });
+        }
+        if self.timeout_secs == Some(0) {
+            errors.push(ValidationError {
+                field: "timeout_secs".to_string(),
+                message: "Timeout must be greater than 0".to_string(),
+            });
+        }
+        if self.max_output_bytes == Some(0) {
+            errors.push(ValidationError {
+                field: "max_output_bytes".to_string(),
+                message: "Output limit must be greater than 0".to_string(),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.timeout_secs.unwrap_or(DEFAULT_EXEC_TIMEOUT_SECS))
+    }
+
+    pub fn max_output_bytes(&self) -> usize {
+        self.max_output_bytes.unwrap_or(DEFAULT_EXEC_MAX_OUTPUT_BYTES)
+    }
+}
+
+/// What a command printed and how it ended
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ExecResult {
+    pub command: String,
+    pub stdout: String,
+    pub stderr: String,
+    /// The exit status, if the command exited normally and the server said so
+    pub exit_status: Option<u32>,
+    /// The signal that killed the command (such as `TERM`), if one did
+    pub exit_signal: Option<String>,
+    /// The output reached `max_output_bytes`, so the rest was discarded and the command stopped
+    pub truncated: bool,
+    /// The command was still running after `timeout_secs` and was stopped; the output is
+    /// what it printed until then
+    pub timed_out: bool,
+    pub duration_ms: u64,
+}
+
+/// How many hosts a batch runs on at once when the request doesn't say
+pub const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+
+/// Which connections a batch runs on
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum BatchTargets {
+    /// These connections, in this order; a saved host's id stands for the connection under it
+    Connections(Vec<String>),
+    /// Every connection or saved host that carries all of these tags, by host name
+    Tags(Vec<String>),
+}
+
+/// The same command, run on many connections
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BatchRequest {
+    /// Id the batch's progress events are sent under; one is generated when unset
+    #[serde(default)]
+    pub batch_id: Option<String>,
+    pub targets: BatchTargets,
+    #[serde(flatten)]
+    pub exec: ExecRequest,
+    /// Hosts the command runs on at once; `DEFAULT_BATCH_CONCURRENCY` when unset
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+}
+
+impl BatchRequest {
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = match self.exec.validate() {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors,
+        };
+
+        if self.max_concurrency == Some(0) {
+            errors.push(ValidationError {
+                field: "max_concurrency".to_string(),
+                message: "Concurrency must be greater than 0".to_string(),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency.unwrap_or(DEFAULT_BATCH_CONCURRENCY)
+    }
+}
+
+/// How a batch's command went on one host
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BatchHostResult {
+    pub connection_id: String,
+    /// The host's name, if the connection exists
+    pub name: Option<String>,
+    /// What the command printed and how it ended, if it ran
+    pub result: Option<ExecResult>,
+    /// Why the command couldn't be run on the host, or didn't finish
+    pub error: Option<SSHError>,
+}
+
+impl BatchHostResult {
+    /// Whether the command ran and exited with status 0
+    pub fn succeeded(&self) -> bool {
+        self.result.as_ref().is_some_and(|result| result.exit_status == Some(0))
+    }
+}
+
+/// The outcome of a batch, one row per host in the order they were targeted
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BatchResult {
+    pub batch_id: String,
+    pub command: String,
+    pub hosts: Vec<BatchHostResult>,
+    /// Hosts where the command exited with status 0
+    pub succeeded: usize,
+    /// Hosts where it didn't, or couldn't be run
+    pub failed: usize,
+    pub duration_ms: u64,
+}
+
 pub type ConnectionMap = HashMap<String, crate::ssh::connection::SSHConnection>;
 
 impl SSHConnectionConfig {
@@ -234,6 +963,69 @@ impl SSHConnectionConfig {
             });
         }
 
+        // Validate connect timeout
+        if self.connect_timeout_secs == Some(0) {
+            errors.push(ValidationError {
+                field: "connect_timeout_secs".to_string(),
+                message: "Connect timeout must be greater than 0".to_string(),
+            });
+        }
+
+        // Validate the reconnect policy and keepalives
+        self.reconnect.validate(&mut errors);
+        self.keepalive.validate(&mut errors);
+        self.scrollback.validate(&mut errors);
+
+        // Validate the jump chain
+        if !self.id.is_empty() && self.jump_hosts.contains(&self.id) {
+            errors.push(ValidationError {
+                field: "jump_hosts".to_string(),
+                message: "A connection cannot jump through itself".to_string(),
+            });
+        }
+        for (index, jump_host) in self.jump_hosts.iter().enumerate() {
+            if self.jump_hosts[..index].contains(jump_host) {
+                errors.push(ValidationError {
+                    field: "jump_hosts".to_string(),
+                    message: format!("Jump host {} is listed more than once", jump_host),
+                });
+            }
+        }
+
+        // Validate the port forwards
+        for (index, forward) in self.local_forwards.iter().enumerate() {
+            if let Err(forward_errors) = forward.validate() {
+                errors.extend(forward_errors.into_iter().map(|error| ValidationError {
+                    field: format!("local_forwards[{}].{}", index, error.field),
+                    message: error.message,
+                }));
+            }
+        }
+        for (index, forward) in self.remote_forwards.iter().enumerate() {
+            if let Err(forward_errors) = forward.validate() {
+                errors.extend(forward_errors.into_iter().map(|error| ValidationError {
+                    field: format!("remote_forwards[{}].{}", index, error.field),
+                    message: error.message,
+                }));
+            }
+        }
+        for (index, forward) in self.dynamic_forwards.iter().enumerate() {
+            if let Err(forward_errors) = forward.validate() {
+                errors.extend(forward_errors.into_iter().map(|error| ValidationError {
+                    field: format!("dynamic_forwards[{}].{}", index, error.field),
+                    message: error.message,
+                }));
+            }
+        }
+
+        // Validate tags
+        if self.tags.iter().any(|tag| tag.trim().is_empty()) {
+            errors.push(ValidationError {
+                field: "tags".to_string(),
+                message: "Tags cannot be empty".to_string(),
+            });
+        }
+
         // Validate username
         if self.username.trim().is_empty() {
             errors.push(ValidationError {
@@ -242,39 +1034,61 @@ impl SSHConnectionConfig {
             });
         }
 
-        // Validate authentication method specific requirements
-        match &self.auth_method {
-            AuthMethod::Password => {
-                if self.password.is_none() || self.password.as_ref().unwrap().is_empty() {
-                    errors.push(ValidationError {
-                        field: "password".to_string(),
-                        message: "Password is required for password authentication".to_string(),
-                    });
-                }
+        // Validate the authentication chain
+        if self.auth_methods.is_empty() {
+            errors.push(ValidationError {
+                field: "auth_methods".to_string(),
+                message: "At least one authentication method is required".to_string(),
+            });
+        }
+        for (index, method) in self.auth_methods.iter().enumerate() {
+            if self.auth_methods[..index].contains(method) {
+                errors.push(ValidationError {
+                    field: "auth_methods".to_string(),
+                    message: format!("{} is listed more than once", method.description()),
+                });
+                continue;
             }
-            AuthMethod::PublicKey => {
-                if let Some(key_path) = &self.private_key_path {
-                    if key_path.trim().is_empty() {
+
+            // Validate authentication method specific requirements
+            match method {
+                AuthMethod::Password => {
+                    let has_password = self.password.as_ref().is_some_and(|p| !p.is_empty())
+                        || self.password_ref.is_some();
+                    if !has_password {
                         errors.push(ValidationError {
-                            field: "private_key_path".to_string(),
-                            message: "Private key path cannot be empty".to_string(),
+                            field: "password".to_string(),
+                            message: "Password is required for password authentication".to_string(),
                         });
-                    } else if !Path::new(key_path).exists() {
+                    }
+                }
+                AuthMethod::PublicKey => {
+                    if let Some(key_path) = &self.private_key_path {
+                        if key_path.trim().is_empty() {
+                            errors.push(ValidationError {
+                                field: "private_key_path".to_string(),
+                                message: "Private key path cannot be empty".to_string(),
+                            });
+                        } else if !Path::new(key_path).exists() {
+                            errors.push(ValidationError {
+                                field: "private_key_path".to_string(),
+                                message: "Private key file does not exist".to_string(),
+                            });
+                        }
+                    } else {
                         errors.push(ValidationError {
                             field: "private_key_path".to_string(),
-                            message: "Private key file does not exist".to_string(),
+                            message: "Private key path is required for public key authentication".to_string(),
                         });
                     }
-                } else {
-                    errors.push(ValidationError {
-                        field: "private_key_path".to_string(),
-                        message: "Private key path is required for public key authentication".to_string(),
-                    });
                 }
-            }
-            AuthMethod::Agent => {
-                // SSH agent authentication doesn't require additional validation
-                // The agent availability will be checked during connection
+                AuthMethod::Agent => {
+                    // SSH agent authentication doesn't require additional validation
+                    // The agent availability will be checked during connection
+                }
+                AuthMethod::KeyboardInteractive => {
+                    // Responses are collected from the user while authenticating
+                }
             }
         }
 
@@ -287,8 +1101,8 @@ impl SSHConnectionConfig {
 
     /// Validates hostname format (basic validation)
     fn is_valid_hostname(&self, hostname: &str) -> bool {
-        // Check if it's a valid IP address
-        if hostname.parse::<IpAddr>().is_ok() {
+        // Check if it's a valid IP address, allowing IPv6 literals in brackets
+        if crate::ssh::resolver::unbracket(hostname).parse::<IpAddr>().is_ok() {
             return true;
         }
 
@@ -311,8 +1125,8 @@ impl SSHConnectionConfig {
 
     /// Validates that the hostname and port combination is reachable
     pub fn validate_connectivity(&self) -> Result<(), ValidationError> {
-        let address = format!("{}:{}", self.hostname, self.port);
-        
+        let address = (crate::ssh::resolver::unbracket(&self.hostname), self.port);
+
         match address.to_socket_addrs() {
             Ok(mut addrs) => {
                 if addrs.next().is_some() {
@@ -331,13 +1145,46 @@ impl SSHConnectionConfig {
         }
     }
 
+    /// Whether the host carries every one of `tags`, ignoring case
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        tags.iter().all(|wanted| self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted)))
+    }
+
+    /// Returns how long to wait for the TCP connection to be established
+    pub fn connect_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.connect_timeout_secs.unwrap_or(DEFAULT_CONNECT_TIMEOUT_SECS))
+    }
+
+    /// Moves any plaintext password and key passphrase into the keyring under this
+    /// connection's id, leaving only references to them in the configuration
+    pub fn store_secrets(&mut self, store: &CredentialStore) -> SSHResult<()> {
+        if let Some(password) = self.password.take().filter(|p| !p.is_empty()) {
+            self.password_ref = Some(store.store(&self.id, SecretKind::Password, &password)?);
+        }
+        if let Some(passphrase) = self.key_passphrase.take().filter(|p| !p.is_empty()) {
+            self.key_passphrase_ref = Some(store.store(&self.id, SecretKind::KeyPassphrase, &passphrase)?);
+        }
+        Ok(())
+    }
+
+    /// Removes this connection's secrets from the keyring. Secrets the store doesn't own,
+    /// such as a saved host's when a connection was opened from it, are left alone.
+    pub fn forget_secrets(&mut self, store: &CredentialStore) -> SSHResult<()> {
+        for credential in [self.password_ref.take(), self.key_passphrase_ref.take()].into_iter().flatten() {
+            if store.owns(&credential) {
+                store.delete(&credential)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Creates a new SSH connection configuration with validation
     pub fn new(
         name: String,
         hostname: String,
         port: u16,
         username: String,
-        auth_method: AuthMethod,
+        auth_methods: Vec<AuthMethod>,
         private_key_path: Option<String>,
         password: Option<String>,
     ) -> Result<Self, Vec<ValidationError>> {
@@ -347,9 +1194,10 @@ impl SSHConnectionConfig {
             hostname,
             port,
             username,
-            auth_method,
+            auth_methods,
             private_key_path,
             password,
+            ..Default::default()
         };
 
         config.validate()?;
@@ -368,7 +1216,7 @@ mod tests {
             "example.com".to_string(),
             22,
             "testuser".to_string(),
-            AuthMethod::Password,
+            vec![AuthMethod::Password],
             None,
             Some("password123".to_string()),
         );
@@ -379,7 +1227,7 @@ mod tests {
         assert_eq!(config.hostname, "example.com");
         assert_eq!(config.port, 22);
         assert_eq!(config.username, "testuser");
-        assert_eq!(config.auth_method, AuthMethod::Password);
+        assert_eq!(config.auth_methods, vec![AuthMethod::Password]);
     }
 
     #[test]
@@ -389,7 +1237,7 @@ mod tests {
             "example.com".to_string(),
             22,
             "testuser".to_string(),
-            AuthMethod::Password,
+            vec![AuthMethod::Password],
             None,
             Some("password123".to_string()),
         );
@@ -406,7 +1254,7 @@ mod tests {
             "".to_string(),
             22,
             "testuser".to_string(),
-            AuthMethod::Password,
+            vec![AuthMethod::Password],
             None,
             Some("password123".to_string()),
         );
@@ -423,7 +1271,7 @@ mod tests {
             "example.com".to_string(),
             0,
             "testuser".to_string(),
-            AuthMethod::Password,
+            vec![AuthMethod::Password],
             None,
             Some("password123".to_string()),
         );
@@ -440,7 +1288,7 @@ mod tests {
             "example.com".to_string(),
             22,
             "testuser".to_string(),
-            AuthMethod::Password,
+            vec![AuthMethod::Password],
             None,
             None,
         );
@@ -457,7 +1305,7 @@ mod tests {
             "example.com".to_string(),
             22,
             "testuser".to_string(),
-            AuthMethod::PublicKey,
+            vec![AuthMethod::PublicKey],
             None,
             None,
         );
@@ -475,14 +1323,15 @@ mod tests {
             hostname: "192.168.1.1".to_string(),
             port: 22,
             username: "user".to_string(),
-            auth_method: AuthMethod::Agent,
-            private_key_path: None,
-            password: None,
+            auth_methods: vec![AuthMethod::Agent],
+            ..Default::default()
         };
         assert!(config.is_valid_hostname("192.168.1.1"));
         assert!(config.is_valid_hostname("example.com"));
         assert!(config.is_valid_hostname("sub.example.com"));
         assert!(config.is_valid_hostname("localhost"));
+        assert!(config.is_valid_hostname("::1"));
+        assert!(config.is_valid_hostname("[2001:db8::1]"));
     }
 
     #[test]
@@ -493,9 +1342,8 @@ mod tests {
             hostname: "".to_string(),
             port: 22,
             username: "user".to_string(),
-            auth_method: AuthMethod::Agent,
-            private_key_path: None,
-            password: None,
+            auth_methods: vec![AuthMethod::Agent],
+            ..Default::default()
         };
         assert!(!config.is_valid_hostname(""));
         assert!(!config.is_valid_hostname("-invalid.com"));
@@ -520,11 +1368,11 @@ mod tests {
         assert_eq!(state.status, ConnectionStatus::Connected);
         assert!(state.is_active());
 
-        state.set_error("Connection failed".to_string());
+        state.set_error(SSHError::NotConnected);
         assert_eq!(state.status, ConnectionStatus::Error);
         assert!(!state.is_active());
         assert!(state.has_error());
-        assert_eq!(state.error, Some("Connection failed".to_string()));
+        assert_eq!(state.error, Some(SSHError::NotConnected));
     }
 
     #[test]
@@ -537,6 +1385,9 @@ mod tests {
 
         assert!(!AuthMethod::Agent.requires_password());
         assert!(!AuthMethod::Agent.requires_private_key());
+
+        assert!(!AuthMethod::KeyboardInteractive.requires_password());
+        assert!(!AuthMethod::KeyboardInteractive.requires_private_key());
     }
 
     #[test]
@@ -550,5 +1401,363 @@ mod tests {
         assert!(ConnectionStatus::Error.is_terminal());
         assert!(!ConnectionStatus::Connected.is_terminal());
         assert!(!ConnectionStatus::Connecting.is_terminal());
+
+        let reconnecting = ConnectionStatus::Reconnecting { attempt: 2 };
+        assert!(reconnecting.is_active());
+        assert!(!reconnecting.is_terminal());
+        assert_eq!(serde_json::to_value(&reconnecting).unwrap(), serde_json::json!({ "Reconnecting": { "attempt": 2 } }));
+    }
+
+    fn password_config() -> SSHConnectionConfig {
+        SSHConnectionConfig::new(
+            "Test Server".to_string(),
+            "example.com".to_string(),
+            22,
+            "testuser".to_string(),
+            vec![AuthMethod::Password],
+            None,
+            Some("password123".to_string()),
+        )
+        .unwrap()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_secrets_not_exposed_by_debug_or_serde() {
+        let mut config = password_config();
+        config.key_passphrase = Some("hunter2".to_string());
+
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains("password123"));
+        assert!(!debug.contains("hunter2"));
+        assert!(debug.contains("<redacted>"));
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("password123"));
+        assert!(!json.contains("hunter2"));
+
+        // Secrets are still accepted from the frontend
+        let incoming = r#"{"id":"","name":"n","hostname":"h","port":22,"username":"u",
+            "auth_method":"Password","private_key_path":null,"password":"from-ui"}"#;
+        let config: SSHConnectionConfig = serde_json::from_str(incoming).unwrap();
+        assert_eq!(config.password.as_deref(), Some("from-ui"));
+    }
+
+    #[test]
+    fn test_store_secrets_leaves_only_references() {
+        let store = CredentialStore::in_memory();
+        let mut config = password_config();
+        config.key_passphrase = Some("hunter2".to_string());
+
+        config.store_secrets(&store).unwrap();
+
+        assert!(config.password.is_none());
+        assert!(config.key_passphrase.is_none());
+        let password_ref = config.password_ref.clone().unwrap();
+        assert_eq!(password_ref, CredentialRef::for_connection(&config.id, SecretKind::Password));
+        assert_eq!(store.load(&password_ref), Ok(Some("password123".to_string())));
+        assert_eq!(
+            store.load(config.key_passphrase_ref.as_ref().unwrap()),
+            Ok(Some("hunter2".to_string()))
+        );
+
+        // A stored password satisfies validation
+        assert!(config.validate().is_ok());
+
+        config.forget_secrets(&store).unwrap();
+        assert!(config.password_ref.is_none());
+        assert_eq!(store.load(&password_ref), Ok(None));
+    }
+
+    #[test]
+    fn test_auth_methods_accepts_single_method_or_list() {
+        let single = r#"{"id":"a","name":"n","hostname":"h","port":22,"username":"u",
+            "auth_method":"Agent","private_key_path":null}"#;
+        let config: SSHConnectionConfig = serde_json::from_str(single).unwrap();
+        assert_eq!(config.auth_methods, vec![AuthMethod::Agent]);
+
+        let chain = r#"{"id":"a","name":"n","hostname":"h","port":22,"username":"u",
+            "auth_methods":["Agent","KeyboardInteractive"],"private_key_path":null}"#;
+        let config: SSHConnectionConfig = serde_json::from_str(chain).unwrap();
+        assert_eq!(config.auth_methods, vec![AuthMethod::Agent, AuthMethod::KeyboardInteractive]);
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["auth_methods"], serde_json::json!(["Agent", "KeyboardInteractive"]));
+    }
+
+    #[test]
+    fn test_auth_chain_validation() {
+        let config = |auth_methods| SSHConnectionConfig::new(
+            "Test Server".to_string(),
+            "example.com".to_string(),
+            22,
+            "testuser".to_string(),
+            auth_methods,
+            None,
+            None,
+        );
+
+        let errors = config(vec![]).unwrap_err();
+        assert!(errors.iter().any(|e| e.field == "auth_methods"));
+
+        let errors = config(vec![AuthMethod::Agent, AuthMethod::Agent]).unwrap_err();
+        assert!(errors.iter().any(|e| e.message.contains("listed more than once")));
+
+        // Every method in the chain must have what it needs, not just the first
+        let errors = config(vec![AuthMethod::Agent, AuthMethod::Password]).unwrap_err();
+        assert!(errors.iter().any(|e| e.field == "password"));
+
+        assert!(config(vec![AuthMethod::Agent, AuthMethod::KeyboardInteractive]).is_ok());
+    }
+
+    #[test]
+    fn test_auth_report_failure_summary() {
+        let report = AuthReport {
+            method: None,
+            failures: vec![
+                AuthFailure { method: AuthMethod::Agent, reason: "no agent".to_string() },
+                AuthFailure { method: AuthMethod::Password, reason: "Invalid credentials".to_string() },
+            ],
+        };
+
+        assert_eq!(
+            report.failure_summary(),
+            "SSH agent authentication: no agent; Password authentication: Invalid credentials"
+        );
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            jitter: 0.0,
+            ..ReconnectPolicy::default()
+        };
+        let delays: Vec<u64> = (1..=7).map(|attempt| policy.delay(attempt, 0.5).as_millis() as u64).collect();
+        assert_eq!(delays, vec![1000, 2000, 4000, 8000, 16_000, 30_000, 30_000]);
+        assert_eq!(policy.delay(u32::MAX, 0.5), std::time::Duration::from_millis(30_000));
+
+        // Jitter only ever shortens the delay, by at most its fraction
+        let policy = ReconnectPolicy::default();
+        assert_eq!(policy.delay(2, 0.0), std::time::Duration::from_millis(2000));
+        assert_eq!(policy.delay(2, 0.5), std::time::Duration::from_millis(1800));
+        for _ in 0..100 {
+            let delay = policy.next_delay(3);
+            assert!(delay <= std::time::Duration::from_millis(4000));
+            assert!(delay >= std::time::Duration::from_millis(3200));
+        }
+    }
+
+    #[test]
+    fn test_reconnect_policy_validation() {
+        let mut config = password_config();
+        config.reconnect = ReconnectPolicy {
+            initial_delay_ms: 5000,
+            max_delay_ms: 1000,
+            multiplier: 0.5,
+            jitter: 2.0,
+            ..ReconnectPolicy::default()
+        };
+        let errors = config.validate().unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["reconnect.max_delay_ms", "reconnect.multiplier", "reconnect.jitter"]);
+
+        // Older configurations without a policy get the default one
+        let json = serde_json::to_value(password_config()).unwrap();
+        let mut object = json.as_object().unwrap().clone();
+        object.remove("reconnect");
+        let config: SSHConnectionConfig = serde_json::from_value(serde_json::Value::Object(object)).unwrap();
+        assert_eq!(config.reconnect, ReconnectPolicy::default());
+    }
+
+    #[test]
+    fn test_local_forward_validation() {
+        let forward: LocalForward = serde_json::from_value(serde_json::json!({
+            "bind_port": 5432,
+            "target_host": "db.internal",
+            "target_port": 5432,
+        }))
+        .unwrap();
+        assert_eq!(forward.bind_address, DEFAULT_BIND_ADDRESS);
+        assert!(!forward.auto_start);
+        assert!(forward.validate().is_ok());
+
+        let mut config = password_config();
+        config.local_forwards = vec![
+            forward.clone(),
+            LocalForward {
+                target_host: " ".to_string(),
+                target_port: 0,
+                ..forward
+            },
+        ];
+        let errors = config.validate().unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["local_forwards[1].target_host", "local_forwards[1].target_port"]);
+    }
+
+    #[test]
+    fn test_remote_forward_validation() {
+        let forward: RemoteForward = serde_json::from_value(serde_json::json!({
+            "bind_port": 0,
+            "target_host": "localhost",
+            "target_port": 3000,
+        }))
+        .unwrap();
+        assert_eq!(forward.bind_address, DEFAULT_REMOTE_BIND_ADDRESS);
+        assert!(forward.validate().is_ok());
+
+        let mut config = password_config();
+        config.remote_forwards = vec![RemoteForward { bind_address: String::new(), ..forward.clone() }];
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors[0].field, "remote_forwards[0].bind_address");
+
+        // Running forwards say which kind of rule they were started from
+        let rule = serde_json::to_value(ForwardRule::Remote(forward)).unwrap();
+        assert_eq!(rule["Remote"]["target_port"], 3000);
+    }
+
+    #[test]
+    fn test_tags_and_batch_requests() {
+        let mut config = password_config();
+        config.tags = vec!["Prod".to_string(), "web".to_string()];
+        assert!(config.has_tags(&["prod".to_string()]));
+        assert!(config.has_tags(&[]));
+        assert!(!config.has_tags(&["web".to_string(), "db".to_string()]));
+
+        config.tags.push(" ".to_string());
+        assert_eq!(config.validate().unwrap_err()[0].field, "tags");
+
+        // The command's options sit alongside the batch's own
+        let request: BatchRequest = serde_json::from_value(serde_json::json!({
+            "targets": { "Tags": ["web"] },
+            "command": "df -h",
+            "timeout_secs": 5,
+        }))
+        .unwrap();
+        assert_eq!(request.targets, BatchTargets::Tags(vec!["web".to_string()]));
+        assert_eq!(request.exec, ExecRequest { timeout_secs: Some(5), ..ExecRequest::new("df -h") });
+        assert_eq!(request.max_concurrency(), DEFAULT_BATCH_CONCURRENCY);
+    }
+
+    #[test]
+    fn test_dynamic_forward_validation() {
+        let forward: DynamicForward = serde_json::from_value(serde_json::json!({ "bind_port": 1080 })).unwrap();
+        assert_eq!(forward.bind_address, DEFAULT_BIND_ADDRESS);
+        assert!(forward.validate().is_ok());
+
+        let mut config = password_config();
+        config.dynamic_forwards = vec![DynamicForward { bind_address: " ".to_string(), ..forward }];
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors[0].field, "dynamic_forwards[0].bind_address");
+    }
+
+    #[test]
+    fn test_keepalive_config() {
+        let mut config = password_config();
+        assert_eq!(config.keepalive.interval(), Some(std::time::Duration::from_secs(15)));
+
+        config.keepalive = KeepaliveConfig { interval_secs: 0, max_missed: 0 };
+        assert_eq!(config.keepalive.interval(), None);
+        assert!(config.validate().is_ok());
+
+        config.keepalive.interval_secs = 5;
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors[0].field, "keepalive.max_missed");
+
+        config.keepalive.max_missed = 1;
+        assert!(config.validate().is_err());
+        config.keepalive.max_missed = 3;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.keepalive.russh_max(), 2);
+    }
+
+    #[test]
+    fn test_scrollback_config() {
+        let mut config = password_config();
+        let parsed: ScrollbackConfig = serde_json::from_str(r#"{"max_lines": 500}"#).unwrap();
+        assert_eq!(parsed, ScrollbackConfig { max_lines: 500, ..ScrollbackConfig::default() });
+
+        config.scrollback = ScrollbackConfig { max_bytes: 0, max_lines: 0 };
+        assert!(config.validate().is_ok());
+
+        config.scrollback.max_bytes = 4096;
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors[0].field, "scrollback.max_lines");
+    }
+
+    #[test]
+    fn test_connect_timeout() {
+        let mut config = password_config();
+        assert_eq!(config.connect_timeout(), std::time::Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS));
+
+        config.connect_timeout_secs = Some(3);
+        assert_eq!(config.connect_timeout(), std::time::Duration::from_secs(3));
+
+        config.connect_timeout_secs = Some(0);
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.field == "connect_timeout_secs"));
+    }
+
+    #[test]
+    fn test_jump_host_validation() {
+        let mut config = password_config();
+        config.jump_hosts = vec!["bastion".to_string(), "inner".to_string()];
+        assert!(config.validate().is_ok());
+
+        config.jump_hosts = vec!["bastion".to_string(), config.id.clone()];
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.message.contains("through itself")));
+
+        config.jump_hosts = vec!["bastion".to_string(), "bastion".to_string()];
+        let errors = config.validate().unwrap_err();
+        assert!(errors.iter().any(|e| e.message.contains("listed more than once")));
+    }
+
+    #[test]
+    fn test_port_forward_serialization() {
+        let forward = PortForward {
+            id: "fwd-1".to_string(),
+            connection_id: "conn-1".to_string(),
+            rule: ForwardRule::Remote(RemoteForward {
+                bind_address: "127.0.0.1".to_string(),
+                bind_port: 0,
+                target_host: "localhost".to_string(),
+                target_port: 3000,
+                auto_start: false,
+            }),
+            listen_address: "127.0.0.1:8022".to_string(),
+            stats: ForwardStats::default(),
+            created_at: chrono::DateTime::UNIX_EPOCH,
+        };
+        assert_eq!(
+            serde_json::to_value(&forward).unwrap(),
+            serde_json::json!({
+                "id": "fwd-1",
+                "connection_id": "conn-1",
+                "rule": { "Remote": {
+                    "bind_address": "127.0.0.1",
+                    "bind_port": 0,
+                    "target_host": "localhost",
+                    "target_port": 3000,
+                    "auto_start": false,
+                } },
+                "listen_address": "127.0.0.1:8022",
+                "stats": { "bytes_sent": 0, "bytes_received": 0, "connections": 0, "active_connections": 0 },
+                "created_at": "1970-01-01T00:00:00Z",
+            })
+        );
+    }
+
+    #[test]
+    fn test_terminal_replay_data_is_base64() {
+        let replay = TerminalReplay {
+            terminal_id: "term-1".to_string(),
+            data: vec![b'o', b'k', 0xff],
+            truncated: false,
+            offset: 3,
+        };
+        assert_eq!(
+            serde_json::to_value(&replay).unwrap(),
+            serde_json::json!({ "terminal_id": "term-1", "data": "b2v/", "truncated": false, "offset": 3 })
+        );
+    }
+}